@@ -0,0 +1,42 @@
+//! Compares recording a pool's connection tags against computing them from its connect options
+//! on every call, which is what `ConnectionTags` used to do.
+
+use std::hint::black_box;
+
+use criterion::{Criterion, criterion_group, criterion_main};
+use sqlx::{ConnectOptions, MySql, Pool, mysql::MySqlConnectOptions, pool::PoolOptions};
+use sqlx_datadog::ConnectionTags;
+use tracing::Span;
+
+/// Records the connection tags of a pool the way it was done before they were cached.
+fn record_uncached(pool: &Pool<MySql>, span: &Span) {
+    let options = pool.connect_options();
+    span.record("peer.hostname", options.get_host());
+    span.record("out.host", options.get_host());
+    span.record("out.port", options.get_port());
+    span.record("db.instance", options.get_database());
+    span.record("db.name", options.get_database());
+    span.record("db.system", options.to_url_lossy().scheme().replace("postgres", "postgresql"));
+}
+
+fn connection_tags(c: &mut Criterion) {
+    let options = MySqlConnectOptions::new()
+        .host("db.example.com")
+        .port(3306)
+        .database("users");
+    // Without timeouts, the pool doesn't spawn any maintenance tasks, so it needs no running
+    // runtime.
+    let pool = PoolOptions::<MySql>::new()
+        .max_lifetime(None)
+        .idle_timeout(None)
+        .connect_lazy_with(options);
+    let span = Span::none();
+
+    let mut group = c.benchmark_group("connection_tags");
+    group.bench_function("cached", |b| b.iter(|| black_box(&pool).record_connection_tags(&span)));
+    group.bench_function("uncached", |b| b.iter(|| record_uncached(black_box(&pool), &span)));
+    group.finish();
+}
+
+criterion_group!(benches, connection_tags);
+criterion_main!(benches);
@@ -0,0 +1,85 @@
+//! Compile errors and warnings of `instrument_query`.
+//!
+//! Arguments are names, not paths:
+//!
+//! ```compile_fail
+//! # #[macro_use] extern crate sqlx_datadog;
+//! #
+//! #[instrument_query(tracing::skip(db))]
+//! async fn count_users(db: &sqlx::MySqlPool) -> Result<i64, sqlx::Error> {
+//!     let query = "SELECT COUNT(*) FROM users";
+//!     sqlx::query_scalar(query).fetch_one(db).await
+//! }
+//! ```
+//!
+//! `db` has to refer to the connection:
+//!
+//! ```compile_fail
+//! # #[macro_use] extern crate sqlx_datadog;
+//! #
+//! #[instrument_query(skip(pool), db = "pool")]
+//! async fn count_users(pool: &sqlx::MySqlPool) -> Result<i64, sqlx::Error> {
+//!     let query = "SELECT COUNT(*) FROM users";
+//!     sqlx::query_scalar(query).fetch_one(pool).await
+//! }
+//! ```
+//!
+//! `query` has to name the query text, not be it:
+//!
+//! ```compile_fail
+//! # #[macro_use] extern crate sqlx_datadog;
+//! #
+//! #[instrument_query(skip(db), query = "SELECT COUNT(*) FROM users")]
+//! async fn count_users(db: &sqlx::MySqlPool) -> Result<i64, sqlx::Error> {
+//!     sqlx::query_scalar("SELECT COUNT(*) FROM users").fetch_one(db).await
+//! }
+//! ```
+//!
+//! Without `db`, there has to be an argument called `db`:
+//!
+//! ```compile_fail
+//! # #[macro_use] extern crate sqlx_datadog;
+//! #
+//! #[instrument_query(skip(pool))]
+//! async fn count_users(pool: &sqlx::MySqlPool) -> Result<i64, sqlx::Error> {
+//!     let query = "SELECT COUNT(*) FROM users";
+//!     sqlx::query_scalar(query).fetch_one(pool).await
+//! }
+//! ```
+//!
+//! Missing query text is a deprecation warning:
+//!
+//! ```compile_fail
+//! #![deny(deprecated)]
+//! # #[macro_use] extern crate sqlx_datadog;
+//! #
+//! #[instrument_query(skip(db))]
+//! async fn count_users(db: &sqlx::MySqlPool, sql: &str) -> Result<i64, sqlx::Error> {
+//!     sqlx::query_scalar(sql).fetch_one(db).await
+//! }
+//! ```
+//!
+//! Which can be silenced:
+//!
+//! ```
+//! #![deny(deprecated)]
+//! # #[macro_use] extern crate sqlx_datadog;
+//! #
+//! #[allow(deprecated)]
+//! #[instrument_query(skip(db))]
+//! async fn count_users(db: &sqlx::MySqlPool, sql: &str) -> Result<i64, sqlx::Error> {
+//!     sqlx::query_scalar(sql).fetch_one(db).await
+//! }
+//! ```
+//!
+//! Or turned into an error using `require_query`:
+//!
+//! ```compile_fail
+//! # #[macro_use] extern crate sqlx_datadog;
+//! #
+//! #[allow(deprecated)]
+//! #[instrument_query(skip(db), require_query)]
+//! async fn count_users(db: &sqlx::MySqlPool, sql: &str) -> Result<i64, sqlx::Error> {
+//!     sqlx::query_scalar(sql).fetch_one(db).await
+//! }
+//! ```
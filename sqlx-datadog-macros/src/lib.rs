@@ -0,0 +1,791 @@
+//! Procedural macros for [`sqlx-datadog`](https://docs.rs/sqlx-datadog).
+//!
+//! Use them through the `sqlx-datadog` crate, which provides the runtime support the generated
+//! code relies on.
+
+use proc_macro::TokenStream;
+use quote::{ToTokens, quote, quote_spanned};
+use syn::{Meta, parse_macro_input, punctuated::Punctuated, visit_mut::VisitMut};
+
+#[cfg(doctest)]
+mod diagnostics;
+mod query;
+
+/// Specialized version of `tracing::instrument` for recording SQLx queries to Datadog.
+///
+/// Accepts all arguments `tracing::instrument` accepts, but patches in extra fields.
+///
+/// By default, expects a function argument called `db` that has a reference to the database
+/// connection. Which tags get recorded for it depends on the backend, see `ConnectionTags`.
+///
+/// If there is a literal string binding called `query` present, its value will be used to set the
+/// relevant span tags. Otherwise the query text is taken from the first SQLx query function or
+/// macro call in the function body, such as `sqlx::query("...")` or `sqlx::query_as!(User, "...")`.
+/// String bindings, `const` items within the function and `concat!` of literals are resolved.
+/// For `sqlx::query_file!` and its variants, the query text is read from the file at compile time.
+///
+/// If no query text can be found, the macro emits a deprecation warning, which can be silenced
+/// using `#[allow(deprecated)]`. Passing `require_query` turns it into an error instead.
+///
+/// The `resource` tag is obfuscated at compile time the way the Datadog agent does it, replacing
+/// literals with `?`, collapsing `IN` lists and whitespace and removing comments, to keep its
+/// cardinality low and data out of it. `db.statement` retains the query text as written, unless
+/// `normalize_statement` is passed, in which case it has its whitespace collapsed and comments
+/// removed as well, but keeps its literals:
+///
+/// ```
+/// # #[macro_use] extern crate sqlx_datadog;
+/// #
+/// #[instrument_query(skip(db), normalize_statement)]
+/// async fn count_active_users(db: &sqlx::MySqlPool) -> Result<i64, sqlx::Error> {
+///     let query = "
+///         SELECT COUNT(*)
+///         FROM users
+///         -- Logged in within the last month.
+///         WHERE last_login > NOW() - INTERVAL 30 DAY
+///     ";
+///     sqlx::query_scalar(query).fetch_one(db).await
+/// }
+/// ```
+///
+/// The query's operation, e.g. `SELECT` or `UPSERT`, and the tables it touches are recorded as
+/// `db.operation` and `db.sql.table` tags, the latter as a comma-separated list.
+///
+/// If the function returns a `Result`, errors are recorded as `error.type`, `error.message` and
+/// `error.stack` tags, using the variant name of `sqlx::Error` as the type. Errors that don't
+/// implement `Display` and `Debug` are not recorded. Errors returned by the
+/// database also get their code, constraint and kind recorded, see `record_error`.
+///
+/// The names of the connection and query binding can be changed using macro parameters, e.g.:
+///
+/// ```
+/// # #[macro_use] extern crate sqlx_datadog;
+/// # use sqlx::Execute;
+/// #
+/// # #[derive(Debug, sqlx::FromRow)]
+/// # struct User { name: String, email: String }
+/// #
+/// #[instrument_query(skip(conn), db = conn, query = my_query)]
+/// async fn fetch_user(conn: &sqlx::MySqlPool, user_id: i64) -> Result<User, sqlx::Error> {
+///     let my_query = "SELECT name, email FROM users WHERE id = ? LIMIT 1";
+///     sqlx::query_as(my_query).bind(user_id).fetch_one(conn).await
+/// }
+/// ```
+///
+/// If `query` names something that is not bound within the function, such as a module-level
+/// `const` or `static`, or a function argument, its value is recorded at runtime instead:
+///
+/// ```
+/// # #[macro_use] extern crate sqlx_datadog;
+/// #
+/// # #[derive(Debug, sqlx::FromRow)]
+/// # struct User { name: String, email: String }
+/// #
+/// const FETCH_USER: &str = "SELECT name, email FROM users WHERE id = ? LIMIT 1";
+///
+/// #[instrument_query(skip(db), query = FETCH_USER)]
+/// async fn fetch_user(db: &sqlx::MySqlPool, user_id: i64) -> Result<User, sqlx::Error> {
+///     sqlx::query_as(FETCH_USER).bind(user_id).fetch_one(db).await
+/// }
+/// ```
+///
+/// Query text that is built at runtime, e.g. using `sqlx::QueryBuilder`, can be recorded using
+/// `record_query`.
+///
+/// # Example
+///
+/// ```
+/// # #[macro_use] extern crate sqlx_datadog;
+/// # use sqlx::Execute;
+/// #
+/// # #[derive(Debug, sqlx::FromRow)]
+/// # struct User { name: String, email: String }
+/// #
+/// #[instrument_query(skip(db))]
+/// async fn fetch_user(db: &sqlx::MySqlPool, user_id: i64) -> Result<User, sqlx::Error> {
+///     let query = "SELECT name, email FROM users WHERE id = ? LIMIT 1";
+///     sqlx::query_as(query).bind(user_id).fetch_one(db).await
+/// }
+/// ```
+///
+/// The query text can also be passed to the query function directly:
+///
+/// ```
+/// # #[macro_use] extern crate sqlx_datadog;
+/// #
+/// # #[derive(Debug, sqlx::FromRow)]
+/// # struct User { name: String, email: String }
+/// #
+/// #[instrument_query(skip(db))]
+/// async fn fetch_users(db: &sqlx::MySqlPool, limit: i64) -> Result<Vec<User>, sqlx::Error> {
+///     const FETCH_USERS: &str = "SELECT name, email FROM users LIMIT ?";
+///     let users = sqlx::query_as(FETCH_USERS).bind(limit).fetch_all(db).await?;
+///     Ok(users)
+/// }
+/// ```
+///
+/// Errors are recorded regardless of the error type, including ones converted using `?`:
+///
+/// ```
+/// # #[macro_use] extern crate sqlx_datadog;
+/// #
+/// #[derive(Debug)]
+/// struct RepoError(sqlx::Error);
+///
+/// impl std::fmt::Display for RepoError {
+///     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+///         write!(f, "repository error: {}", self.0)
+///     }
+/// }
+///
+/// impl From<sqlx::Error> for RepoError {
+///     fn from(error: sqlx::Error) -> Self {
+///         Self(error)
+///     }
+/// }
+///
+/// #[instrument_query(skip(db))]
+/// async fn delete_user(db: &sqlx::MySqlPool, user_id: i64) -> Result<(), RepoError> {
+///     let query = "DELETE FROM users WHERE id = ?";
+///     sqlx::query(query).bind(user_id).execute(db).await?;
+///     Ok(())
+/// }
+/// ```
+///
+/// Return types that can't be recorded, or named, work as before:
+///
+/// ```
+/// # #[macro_use] extern crate sqlx_datadog;
+/// #
+/// #[instrument_query(skip(db))]
+/// async fn check_user<'a>(db: &sqlx::MySqlPool, name: &'a str) -> Result<(), &'a str> {
+///     let query = "SELECT COUNT(*) FROM users WHERE name = ?";
+///     let count: i64 = sqlx::query_scalar(query).bind(name).fetch_one(db).await.map_err(|_| name)?;
+///     if count > 0 { Ok(()) } else { Err(name) }
+/// }
+///
+/// #[instrument_query(skip(db))]
+/// async fn user_ids(db: &sqlx::MySqlPool) -> Result<impl Iterator<Item = i64>, ()> {
+///     let query = "SELECT id FROM users";
+///     let ids: Vec<i64> = sqlx::query_scalar(query).fetch_all(db).await.map_err(|_| ())?;
+///     Ok(ids.into_iter())
+/// }
+/// ```
+///
+/// The return type guides coercions within the body as usual, e.g. of boxed errors and borrows:
+///
+/// ```
+/// # #[macro_use] extern crate sqlx_datadog;
+/// #
+/// #[derive(Debug)]
+/// struct InvalidLimit;
+///
+/// impl std::fmt::Display for InvalidLimit {
+///     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+///         write!(f, "invalid limit")
+///     }
+/// }
+///
+/// impl std::error::Error for InvalidLimit {}
+///
+/// #[instrument_query(skip(db))]
+/// async fn count_users(db: &sqlx::MySqlPool, limit: i64) -> Result<i64, Box<dyn std::error::Error + Send + Sync>> {
+///     let query = "SELECT COUNT(*) FROM (SELECT id FROM users LIMIT ?) AS u";
+///     if limit < 0 {
+///         return Err(Box::new(InvalidLimit));
+///     }
+///     Ok(sqlx::query_scalar(query).bind(limit).fetch_one(db).await?)
+/// }
+///
+/// struct Users {
+///     pool: sqlx::MySqlPool,
+///     table: String,
+/// }
+///
+/// impl Users {
+///     #[instrument_query(skip(self), db = self.pool)]
+///     async fn table(&self) -> Result<&str, sqlx::Error> {
+///         let query = "SELECT 1";
+///         sqlx::query(query).execute(&self.pool).await?;
+///         Ok(&self.table)
+///     }
+/// }
+/// ```
+///
+/// This includes results borrowing from arguments of synchronous functions:
+///
+/// ```
+/// # #[macro_use] extern crate sqlx_datadog;
+/// #
+/// #[instrument_query(skip(db, buf))]
+/// fn render_query<'a>(db: &sqlx::MySqlPool, buf: &'a mut String) -> Result<&'a str, ()> {
+///     let query = "SELECT name FROM users";
+///     buf.push_str(query);
+///     Ok(&*buf)
+/// }
+/// ```
+///
+/// Passing `row_count` records the number of rows returned or affected as `db.row_count`, for
+/// functions returning a `Vec`, an `Option` or a query result, see `RowCount`:
+///
+/// ```
+/// # #[macro_use] extern crate sqlx_datadog;
+/// #
+/// #[instrument_query(skip(db), row_count)]
+/// async fn deactivate_users(db: &sqlx::MySqlPool) -> Result<sqlx::mysql::MySqlQueryResult, sqlx::Error> {
+///     let query = "UPDATE users SET active = FALSE WHERE last_login < NOW() - INTERVAL 1 YEAR";
+///     sqlx::query(query).execute(db).await
+/// }
+/// ```
+///
+/// Passing `capture_params` records the values bound to the query, using `.bind()` calls chained
+/// onto the query function, or the arguments of SQLx query macros, as `db.sql.params.1`,
+/// `db.sql.params.2` and so on. To keep personal data out of traces, values are redacted to `?`
+/// unless they are function arguments, or fields of them, listed in `allow`, which records their
+/// `Debug` representation truncated to `max_len` characters (64 by default), or in `hash`, which
+/// records a digest keyed with the secret set using `set_param_digest_key` instead:
+///
+/// ```
+/// # #[macro_use] extern crate sqlx_datadog;
+/// #
+/// # #[derive(Debug, sqlx::FromRow)]
+/// # struct User { name: String, email: String }
+/// #
+/// #[instrument_query(skip(db), capture_params(allow(limit), hash(email), max_len = 32))]
+/// async fn fetch_users(db: &sqlx::MySqlPool, email: &str, limit: i64) -> Result<Vec<User>, sqlx::Error> {
+///     let query = "SELECT name, email FROM users WHERE email = ? LIMIT ?";
+///     sqlx::query_as(query).bind(email).bind(limit).fetch_all(db).await
+/// }
+/// ```
+///
+/// Arguments are recorded as they were passed to the function, so those rebound within it, e.g.
+/// using `let id = id + 1`, are redacted as well. Span fields can't be numbered, so this requires
+/// the `opentelemetry` feature, which records them as attributes of the OpenTelemetry span.
+/// Without it, the macro emits a deprecation warning.
+///
+/// The database system is derived from the backend. Databases that share a backend with another,
+/// like MariaDB, can set it using `system`, which also accepts SQLx URL schemes like `postgres`:
+///
+/// ```
+/// # #[macro_use] extern crate sqlx_datadog;
+/// #
+/// #[instrument_query(skip(db), system = "mariadb")]
+/// async fn count_users(db: &sqlx::MySqlPool) -> Result<i64, sqlx::Error> {
+///     let query = "SELECT COUNT(*) FROM users";
+///     sqlx::query_scalar(query).fetch_one(db).await
+/// }
+/// ```
+///
+/// To show the database as a separate service in the Datadog service map, set its `peer.service`
+/// using `peer_service`, or for all queries using `set_default_peer_service`. Alternatively,
+/// `service` overrides the service name of the span itself:
+///
+/// ```
+/// # #[macro_use] extern crate sqlx_datadog;
+/// #
+/// #[instrument_query(skip(db), peer_service = "users-db")]
+/// async fn count_users(db: &sqlx::MySqlPool) -> Result<i64, sqlx::Error> {
+///     let query = "SELECT COUNT(*) FROM users";
+///     sqlx::query_scalar(query).fetch_one(db).await
+/// }
+/// ```
+///
+/// The span's `operation` and `component` default to `sqlx.query` and `sqlx`, and can be set to
+/// match other tracing libraries, either as a string, or a path to a `const`, e.g. to share them
+/// across a module:
+///
+/// ```
+/// # #[macro_use] extern crate sqlx_datadog;
+/// #
+/// const OPERATION: &str = "mysql.query";
+///
+/// #[instrument_query(skip(db), operation = OPERATION, component = "mysql")]
+/// async fn count_users(db: &sqlx::MySqlPool) -> Result<i64, sqlx::Error> {
+///     let query = "SELECT COUNT(*) FROM users";
+///     sqlx::query_scalar(query).fetch_one(db).await
+/// }
+/// ```
+///
+/// SQLite pools are tagged with the database file instead of a host and port:
+///
+/// ```
+/// # #[macro_use] extern crate sqlx_datadog;
+/// #
+/// #[instrument_query(skip(db))]
+/// async fn count_users(db: &sqlx::SqlitePool) -> Result<i64, sqlx::Error> {
+///     let query = "SELECT COUNT(*) FROM users";
+///     sqlx::query_scalar(query).fetch_one(db).await
+/// }
+/// ```
+#[proc_macro_attribute]
+pub fn instrument_query(args: TokenStream, item: TokenStream) -> TokenStream {
+    let args = parse_macro_input!(args with Punctuated::<Meta, syn::Token![,]>::parse_terminated);
+    let input_fn = parse_macro_input!(item as syn::ItemFn);
+
+    expand(args, input_fn)
+        .unwrap_or_else(syn::Error::into_compile_error)
+        .into()
+}
+
+/// Expands `instrument_query`, see there.
+fn expand(args: Punctuated<Meta, syn::Token![,]>, mut input_fn: syn::ItemFn) -> syn::Result<proc_macro2::TokenStream> {
+    let mut instrument_args: Vec<Meta> = vec![];
+    let mut fields = vec![];
+    let mut db_arg = None;
+    let mut query_arg = None;
+    let mut system_arg = None;
+    let mut service_arg = None;
+    let mut peer_service_arg = None;
+    let mut operation_arg = None;
+    let mut component_arg = None;
+    let mut row_count = None;
+    let mut capture_params = None;
+    let mut normalize_statement = false;
+    let mut require_query = false;
+
+    for arg in args {
+        let Some(name) = arg.path().get_ident() else {
+            return Err(syn::Error::new_spanned(arg.path(), "expected an argument name, not a path"));
+        };
+
+        if name == "db" {
+            let value = &arg.require_name_value()?.value;
+            validate_db(value)?;
+            if db_arg.replace(value.clone()).is_some() {
+                return Err(syn::Error::new_spanned(name, "duplicate `db` argument"));
+            }
+        } else if name == "query" {
+            let value = &arg.require_name_value()?.value;
+            validate_query(value)?;
+            if query_arg.replace(value.clone()).is_some() {
+                return Err(syn::Error::new_spanned(name, "duplicate `query` argument"));
+            }
+        } else if name == "system" {
+            let system = parse_system(&arg.require_name_value()?.value)?;
+            if system_arg.replace(system).is_some() {
+                return Err(syn::Error::new_spanned(name, "duplicate `system` argument"));
+            }
+        } else if name == "service" || name == "peer_service" {
+            let service = parse_service(name, &arg.require_name_value()?.value)?;
+            let service_arg = if name == "service" { &mut service_arg } else { &mut peer_service_arg };
+            if service_arg.replace(service).is_some() {
+                return Err(syn::Error::new_spanned(name, format!("duplicate `{name}` argument")));
+            }
+        } else if name == "operation" || name == "component" {
+            let value = &arg.require_name_value()?.value;
+            validate_name(name, value)?;
+            let name_arg = if name == "operation" { &mut operation_arg } else { &mut component_arg };
+            if name_arg.replace(value.clone()).is_some() {
+                return Err(syn::Error::new_spanned(name, format!("duplicate `{name}` argument")));
+            }
+        } else if name == "fields" {
+            fields.push(arg.require_list()?.tokens.clone());
+        } else if name == "row_count" {
+            row_count = Some(arg.require_path_only()?.clone());
+        } else if name == "capture_params" {
+            let capture = parse_capture_params(&arg, &input_fn.sig)?;
+            if capture_params.replace(capture).is_some() {
+                return Err(syn::Error::new_spanned(name, "duplicate `capture_params` argument"));
+            }
+        } else if name == "require_query" {
+            arg.require_path_only()?;
+            require_query = true;
+        } else if name == "normalize_statement" {
+            arg.require_path_only()?;
+            normalize_statement = true;
+        } else {
+            instrument_args.push(arg);
+        }
+    }
+
+    // Without an override, the connection has to be a parameter, as it's used before the body.
+    if db_arg.is_none() && !has_param(&input_fn.sig, "db") {
+        return Err(syn::Error::new_spanned(
+            &input_fn.sig,
+            "expected a function argument called `db` holding the database connection; \
+             use the `db` option to refer to it by another name, e.g. `#[instrument_query(db = pool)]`",
+        ));
+    }
+    let db_ident = db_arg.map_or_else(|| quote! { db }, ToTokens::into_token_stream);
+    let query_overridden = query_arg.is_some();
+    let query_ident = query_arg.map_or_else(|| quote! { query }, ToTokens::into_token_stream);
+
+    // Find the query text.
+    // A query that isn't bound within the function, like a module-level const, can only be
+    // recorded at runtime.
+    let query_runtime = query_overridden && !query::is_bound(&input_fn.block, &query_ident.to_string());
+    let query_literal = if query_runtime {
+        None
+    } else {
+        query::find_query(&input_fn.block, &query_ident.to_string())
+    };
+
+    // Binds have to be found before the body is wrapped below.
+    let params = if capture_params.is_some() {
+        query::find_params(&input_fn.block, &query_ident.to_string())
+    } else {
+        vec![]
+    };
+    // Arguments are recorded as passed, so if they are rebound, the value bound may be another.
+    if let Some(capture) = &mut capture_params {
+        let block = &input_fn.block;
+        capture.allow.retain(|name| !query::is_bound(block, &name.to_string()));
+        capture.hash.retain(|name| !query::is_bound(block, &name.to_string()));
+    }
+
+    // Make missing query text visible, as a warning by default, or an error if it is required.
+    let mut missing_query_warning = None;
+    if query_literal.is_none() && !query_runtime && !query::calls(&input_fn.block, "record_query") {
+        let message = if query::is_bound(&input_fn.block, &query_ident.to_string()) {
+            format!(
+                "the `{query_ident}` binding is not a string literal, so `resource` and `db.statement` \
+                 can't be recorded; record it using `sqlx_datadog::record_query` instead"
+            )
+        } else {
+            "could not find the query text, so `resource` and `db.statement` can't be recorded; \
+             bind it to `query`, point to it using the `query` option, or record it using \
+             `sqlx_datadog::record_query`".to_string()
+        };
+        if require_query {
+            return Err(syn::Error::new_spanned(&input_fn.sig.ident, message));
+        }
+        // Proc macros can't emit warnings, but using a deprecated item does.
+        let span = input_fn.sig.ident.span();
+        missing_query_warning = Some(quote_spanned! {span=>
+            {
+                #[deprecated(note = #message)]
+                struct QueryTextNotFound;
+                let _ = QueryTextNotFound;
+            }
+        });
+    }
+
+    // If the function returns a result, record errors on the span.
+    let result_type = match &input_fn.sig.output {
+        syn::ReturnType::Type(_, return_type) if is_result(return_type) => {
+            // `impl Trait` can't be named within the body, so it is left to inference.
+            let mut return_type = return_type.clone();
+            InferImplTrait.visit_type_mut(&mut return_type);
+            Some(return_type)
+        }
+        _ => None,
+    };
+    if let Some(path) = &row_count && result_type.is_none() {
+        return Err(syn::Error::new_spanned(path, "`row_count` requires a function returning a `Result`"));
+    }
+    if let Some(return_type) = result_type {
+        let block = &input_fn.block;
+        let body = if input_fn.sig.asyncness.is_some() {
+            // Like `tracing::instrument`, fix the output type before the body, so it guides coercions.
+            let stmts = &block.stmts;
+            quote! {
+                async move {
+                    #[allow(unreachable_code, clippy::diverging_sub_expression, clippy::needless_return)]
+                    if false {
+                        let __sqlx_datadog_return: #return_type = loop {};
+                        return __sqlx_datadog_return;
+                    }
+                    #(#stmts)*
+                }
+                .await
+            }
+        } else {
+            quote! { ::sqlx_datadog::__private::call_once(move || -> #return_type #block) }
+        };
+        let record_row_count = row_count.is_some().then(|| quote! {
+            if let ::std::result::Result::Ok(value) = &__sqlx_datadog_result {
+                ::tracing::Span::current().record("db.row_count", ::sqlx_datadog::RowCount::row_count(value));
+            }
+        });
+        input_fn.block = syn::parse_quote! {{
+            let __sqlx_datadog_result = #body;
+            if let ::std::result::Result::Err(error) = &__sqlx_datadog_result {
+                #[allow(unused_imports)]
+                use ::sqlx_datadog::__private::{RecordDisplayError as _, RecordSqlxError as _, SkipError as _};
+                (&&&::sqlx_datadog::__private::ErrorTags(error)).record_error(&::tracing::Span::current());
+            }
+            #record_row_count
+            __sqlx_datadog_result
+        }};
+    }
+
+    // These are in reverse.
+    let mut injected_tags = vec![
+        quote! { #db_ident.record_connection_tags(&::tracing::Span::current()); },
+        quote! { use ::sqlx_datadog::ConnectionTags as _; },
+    ];
+
+    // This has to come after the connection tags, to override their database system.
+    if let Some(system) = system_arg {
+        injected_tags.insert(0, quote! { ::tracing::Span::current().record("db.system", #system); });
+    }
+
+    if let Some(service) = service_arg {
+        injected_tags.insert(0, quote! { ::tracing::Span::current().record("service", #service); });
+    }
+    if let Some(peer_service) = peer_service_arg {
+        injected_tags.insert(0, quote! { ::tracing::Span::current().record("peer.service", #peer_service); });
+    } else {
+        injected_tags.insert(0, quote! { ::sqlx_datadog::__private::record_default_peer_service(&::tracing::Span::current()); });
+    }
+
+    // If we know the query text, inject it into the span tags.
+    if let Some(query_lit) = query_literal {
+        let statement = if normalize_statement {
+            let statement = sqlx_datadog_sql::normalize(&query_lit.value());
+            quote! { #statement }
+        } else {
+            quote! { #query_lit.trim() }
+        };
+        injected_tags.insert(0, quote! { ::tracing::Span::current().record("db.statement", #statement); });
+        let resource = sqlx_datadog_sql::obfuscate(&query_lit.value());
+        injected_tags.insert(0, quote! { ::tracing::Span::current().record("resource", #resource); });
+        if let Some(operation) = sqlx_datadog_sql::operation(&query_lit.value()) {
+            injected_tags.insert(0, quote! { ::tracing::Span::current().record("db.operation", #operation); });
+        }
+        let tables = sqlx_datadog_sql::tables(&query_lit.value()).join(",");
+        if !tables.is_empty() {
+            injected_tags.insert(0, quote! { ::tracing::Span::current().record("db.sql.table", #tables); });
+        }
+    } else if query_runtime {
+        if normalize_statement {
+            injected_tags.insert(0, quote! { ::tracing::Span::current().record("db.statement", ::sqlx_datadog::__private::normalize(::std::convert::AsRef::<str>::as_ref(&#query_ident))); });
+        }
+        injected_tags.insert(0, quote! { ::sqlx_datadog::record_query(&::tracing::Span::current(), ::std::convert::AsRef::<str>::as_ref(&#query_ident)); });
+    }
+
+    // Only arguments are known upfront, anything else bound is redacted.
+    if let Some(capture) = capture_params {
+        let params = params.iter().map(|param| {
+            let (value, policy) = match param_argument(param) {
+                Some((name, value)) if capture.allow.contains(name) => {
+                    let max_len = capture.max_len;
+                    (quote! { #value }, quote! { Value { max_len: #max_len } })
+                }
+                Some((name, value)) if capture.hash.contains(name) => (quote! { #value }, quote! { Digest }),
+                _ => (quote! { () }, quote! { Redacted }),
+            };
+            quote! { (&#value as &dyn ::std::fmt::Debug, ::sqlx_datadog::__private::Capture::#policy) }
+        });
+        injected_tags.insert(0, quote! {
+            ::sqlx_datadog::__private::capture_params(&::tracing::Span::current(), &[#(#params),*]);
+        });
+    }
+
+    injected_tags.extend(missing_query_warning);
+
+    for tag in injected_tags {
+        input_fn.block.stmts.insert(0, syn::parse2(tag)?);
+    }
+
+    let operation = operation_arg.map_or_else(|| quote! { "sqlx.query" }, ToTokens::into_token_stream);
+    let component = component_arg.map_or_else(|| quote! { "sqlx" }, ToTokens::into_token_stream);
+    let instrument_attr = quote! {
+        #[::tracing::instrument(
+            fields(
+                span.kind = "client",
+                span.type = "sql",
+                component = #component,
+                operation = #operation,
+                resource,
+                service,
+                peer.service,
+                peer.db.name,
+                peer.hostname,
+                out.host,
+                out.port,
+                db.system,
+                db.instance,
+                db.name,
+                db.statement,
+                db.operation,
+                db.sql.table,
+                error.type,
+                error.message,
+                error.stack,
+                db.error.code,
+                db.error.constraint,
+                db.error.kind,
+                db.row_count,
+                db.pool.wait_ms,
+                _dd.dbm_trace_injected,
+                #(#fields),*
+            )
+            #(#instrument_args),*
+        )]
+    };
+
+    Ok(quote! {
+        #instrument_attr
+        #input_fn
+    })
+}
+
+/// Checks whether a function has a parameter with the given name.
+fn has_param(sig: &syn::Signature, name: &str) -> bool {
+    sig.inputs.iter().any(|input| {
+        if let syn::FnArg::Typed(pat_type) = input &&
+            let syn::Pat::Ident(pat_ident) = &*pat_type.pat {
+                pat_ident.ident == name
+        } else {
+            false
+        }
+    })
+}
+
+/// Checks that the `db` argument is an expression which can refer to a database connection.
+fn validate_db(value: &syn::Expr) -> syn::Result<()> {
+    match value {
+        syn::Expr::Path(_) |
+        syn::Expr::Field(_) |
+        syn::Expr::MethodCall(_) |
+        syn::Expr::Call(_) |
+        syn::Expr::Index(_) |
+        syn::Expr::Reference(_) |
+        syn::Expr::Unary(_) |
+        syn::Expr::Paren(_) => Ok(()),
+        _ => Err(syn::Error::new_spanned(
+            value,
+            "expected an expression referring to the database connection, e.g. `db = conn` or `db = self.pool`",
+        )),
+    }
+}
+
+/// Checks that the `query` argument names a binding, or a path or field holding the query text.
+fn validate_query(value: &syn::Expr) -> syn::Result<()> {
+    match value {
+        syn::Expr::Path(_) | syn::Expr::Field(_) => Ok(()),
+        _ => Err(syn::Error::new_spanned(
+            value,
+            "expected the name of the query binding or a path to the query text, e.g. `query = my_query` or `query = FETCH_USER`",
+        )),
+    }
+}
+
+/// Parses the `system` argument, mapping SQLx URL schemes to the database systems they stand for.
+fn parse_system(value: &syn::Expr) -> syn::Result<String> {
+    if let syn::Expr::Lit(syn::ExprLit { lit: syn::Lit::Str(lit), .. }) = value &&
+        !lit.value().trim().is_empty() {
+            let system = lit.value().trim().to_ascii_lowercase();
+            Ok(sqlx_datadog_sql::db_system(&system).map_or(system, ToString::to_string))
+    } else {
+        Err(syn::Error::new_spanned(
+            value,
+            "expected the name of the database system as a string, e.g. `system = \"postgresql\"`",
+        ))
+    }
+}
+
+/// Checks that an `operation` or `component` argument is a string, or a path to one.
+fn validate_name(name: &syn::Ident, value: &syn::Expr) -> syn::Result<()> {
+    let example = if name == "operation" { "mysql.query" } else { "mysql" };
+    match value {
+        syn::Expr::Lit(syn::ExprLit { lit: syn::Lit::Str(_), .. }) | syn::Expr::Path(_) => Ok(()),
+        _ => Err(syn::Error::new_spanned(
+            value,
+            format!("expected a string or a path to a `const` holding one, e.g. `{name} = \"{example}\"`"),
+        )),
+    }
+}
+
+/// Parses a `service` or `peer_service` argument, which has to be a service name.
+fn parse_service(name: &syn::Ident, value: &syn::Expr) -> syn::Result<syn::LitStr> {
+    match value {
+        syn::Expr::Lit(syn::ExprLit { lit: syn::Lit::Str(lit), .. }) if !lit.value().trim().is_empty() => Ok(lit.clone()),
+        _ => Err(syn::Error::new_spanned(
+            value,
+            format!("expected the service name as a string, e.g. `{name} = \"users-db\"`"),
+        )),
+    }
+}
+
+/// The redaction policy of `capture_params`.
+struct CaptureParams {
+    /// Arguments whose values are recorded.
+    allow: Vec<syn::Ident>,
+    /// Arguments whose values are recorded as a digest.
+    hash: Vec<syn::Ident>,
+    /// The maximum number of characters recorded per value.
+    max_len: usize,
+}
+
+/// Parses the `capture_params` argument, e.g. `capture_params(allow(limit), hash(email), max_len = 32)`.
+fn parse_capture_params(arg: &Meta, sig: &syn::Signature) -> syn::Result<CaptureParams> {
+    let mut capture = CaptureParams { allow: vec![], hash: vec![], max_len: 64 };
+    let options = match arg {
+        Meta::Path(_) => return Ok(capture),
+        Meta::List(list) => list.parse_args_with(Punctuated::<Meta, syn::Token![,]>::parse_terminated)?,
+        Meta::NameValue(_) => return Err(syn::Error::new_spanned(
+            arg,
+            "expected a list of options, e.g. `capture_params(allow(limit), hash(email))`",
+        )),
+    };
+
+    for option in options {
+        if option.path().is_ident("allow") || option.path().is_ident("hash") {
+            let names = option
+                .require_list()?
+                .parse_args_with(Punctuated::<syn::Ident, syn::Token![,]>::parse_terminated)?;
+            for name in names {
+                if !has_param(sig, &name.to_string()) {
+                    return Err(syn::Error::new_spanned(&name, format!("`{name}` is not an argument of this function")));
+                }
+                if capture.allow.contains(&name) || capture.hash.contains(&name) {
+                    return Err(syn::Error::new_spanned(&name, format!("duplicate `{name}` in `capture_params`")));
+                }
+                if option.path().is_ident("allow") {
+                    capture.allow.push(name);
+                } else {
+                    capture.hash.push(name);
+                }
+            }
+        } else if option.path().is_ident("max_len") {
+            match &option.require_name_value()?.value {
+                syn::Expr::Lit(syn::ExprLit { lit: syn::Lit::Int(lit), .. }) => capture.max_len = lit.base10_parse()?,
+                value => return Err(syn::Error::new_spanned(value, "expected a number of characters, e.g. `max_len = 32`")),
+            }
+        } else {
+            return Err(syn::Error::new_spanned(
+                option.path(),
+                "unknown `capture_params` option, expected `allow`, `hash` or `max_len`",
+            ));
+        }
+    }
+    Ok(capture)
+}
+
+/// Returns the function argument a bound expression refers to, and the expression without
+/// references, if it is the argument or one of its fields.
+fn param_argument(expr: &syn::Expr) -> Option<(&syn::Ident, &syn::Expr)> {
+    match expr {
+        syn::Expr::Reference(reference) => param_argument(&reference.expr),
+        syn::Expr::Paren(paren) => param_argument(&paren.expr),
+        syn::Expr::Group(group) => param_argument(&group.expr),
+        syn::Expr::Path(path) => Some((path.path.get_ident()?, expr)),
+        syn::Expr::Field(field) => Some((param_argument(&field.base)?.0, expr)),
+        _ => None,
+    }
+}
+
+/// Replaces `impl Trait` types with `_`.
+struct InferImplTrait;
+
+impl VisitMut for InferImplTrait {
+    fn visit_type_mut(&mut self, ty: &mut syn::Type) {
+        if let syn::Type::ImplTrait(impl_trait) = ty {
+            *ty = syn::Type::Infer(syn::TypeInfer { underscore_token: syn::Token![_](impl_trait.impl_token.span) });
+        } else {
+            syn::visit_mut::visit_type_mut(self, ty);
+        }
+    }
+}
+
+/// Checks whether a type looks like a `Result`, including aliases like `sqlx::Result`.
+fn is_result(ty: &syn::Type) -> bool {
+    if let syn::Type::Path(type_path) = ty &&
+        let Some(segment) = type_path.path.segments.last() {
+            segment.ident == "Result"
+    } else {
+        false
+    }
+}
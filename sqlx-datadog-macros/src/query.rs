@@ -0,0 +1,431 @@
+use std::{collections::HashMap, env, fs, path::Path};
+
+use syn::{
+    Expr, ExprCall, ExprMacro, ExprMethodCall, Lit, LitStr, Local, Macro, Pat, Token, punctuated::Punctuated,
+    visit::Visit,
+};
+
+/// Functions taking the query text as their first argument.
+const QUERY_FUNCTIONS: &[&str] = &[
+    "query",
+    "query_as",
+    "query_scalar",
+    "query_with",
+    "query_as_with",
+    "query_scalar_with",
+];
+
+/// Macros taking the query text as their first string literal argument.
+const QUERY_MACROS: &[&str] = &[
+    "query",
+    "query_as",
+    "query_scalar",
+    "query_unchecked",
+    "query_as_unchecked",
+    "query_scalar_unchecked",
+];
+
+/// Macros taking the path of a file containing the query text as their first string literal
+/// argument, relative to the crate's manifest directory.
+const QUERY_FILE_MACROS: &[&str] = &[
+    "query_file",
+    "query_file_as",
+    "query_file_scalar",
+    "query_file_unchecked",
+    "query_file_as_unchecked",
+    "query_file_scalar_unchecked",
+];
+
+/// Finds the query text in a function body.
+///
+/// Prefers a string binding with the given name, falling back to the first SQLx query function
+/// or macro call in the body. Calls passing a string binding or a `const` defined within the body
+/// are resolved to its value.
+pub(crate) fn find_query(block: &syn::Block, binding: &str) -> Option<LitStr> {
+    let mut visitor = QueryVisitor::default();
+    visitor.visit_block(block);
+
+    if let Some(query) = visitor.bindings.remove(binding) {
+        return Some(query);
+    }
+
+    visitor.calls.iter().find_map(|call| visitor.resolve(&call.arg))
+}
+
+/// Finds the arguments bound to the query `find_query` finds, in order.
+///
+/// These are the arguments of `.bind()` calls chained onto the query function call, or those
+/// following the query text in SQLx query macros. The query is the first call passing the given
+/// binding, or the first call whose query text is known.
+pub(crate) fn find_params(block: &syn::Block, binding: &str) -> Vec<Expr> {
+    let mut visitor = QueryVisitor::default();
+    visitor.visit_block(block);
+
+    let mut calls = visitor.calls.iter();
+    let call = calls
+        .clone()
+        .find(|call| matches!(&call.arg, QueryArg::Binding(name) if name == binding))
+        .or_else(|| calls.find(|call| visitor.resolve(&call.arg).is_some()));
+    call.map(|call| call.params.clone()).unwrap_or_default()
+}
+
+/// Checks whether a name is bound by a `let`, `const` or `static` within a function body.
+pub(crate) fn is_bound(block: &syn::Block, name: &str) -> bool {
+    let mut visitor = BindingVisitor { name, found: false };
+    visitor.visit_block(block);
+    visitor.found
+}
+
+/// Checks whether a function with the given name is called within a function body.
+pub(crate) fn calls(block: &syn::Block, name: &str) -> bool {
+    let mut visitor = CallVisitor { name, found: false };
+    visitor.visit_block(block);
+    visitor.found
+}
+
+/// A query text argument, either as a literal or a reference to a binding.
+enum QueryArg {
+    Literal(LitStr),
+    Binding(String),
+}
+
+/// A call of an SQLx query function or macro.
+struct QueryCall {
+    arg: QueryArg,
+    /// The arguments bound to the query.
+    params: Vec<Expr>,
+}
+
+/// Collects string bindings and query calls from a function body.
+#[derive(Default)]
+struct QueryVisitor {
+    bindings: HashMap<String, LitStr>,
+    calls: Vec<QueryCall>,
+}
+
+impl QueryVisitor {
+    /// Returns the query text of a query argument, if it is known.
+    fn resolve(&self, arg: &QueryArg) -> Option<LitStr> {
+        match arg {
+            QueryArg::Literal(query) => Some(query.clone()),
+            QueryArg::Binding(name) => self.bindings.get(name).cloned(),
+        }
+    }
+}
+
+impl<'ast> Visit<'ast> for QueryVisitor {
+    fn visit_local(&mut self, local: &'ast Local) {
+        if let Pat::Ident(pat_ident) = &local.pat &&
+            let Some(init) = &local.init &&
+            let Some(value) = string_value(&init.expr) {
+                self.bindings.entry(pat_ident.ident.to_string()).or_insert(value);
+        }
+        syn::visit::visit_local(self, local);
+    }
+
+    fn visit_item_const(&mut self, item: &'ast syn::ItemConst) {
+        if let Some(value) = string_value(&item.expr) {
+            self.bindings.entry(item.ident.to_string()).or_insert(value);
+        }
+        syn::visit::visit_item_const(self, item);
+    }
+
+    fn visit_expr_call(&mut self, call: &'ast ExprCall) {
+        if let Some(arg) = query_function_arg(call) {
+            self.calls.push(QueryCall { arg, params: vec![] });
+        }
+        syn::visit::visit_expr_call(self, call);
+    }
+
+    fn visit_expr_method_call(&mut self, call: &'ast ExprMethodCall) {
+        // Walk the whole chain at once, to attribute its `.bind()` calls to the query it starts with.
+        let mut params = vec![];
+        let mut call = call;
+        loop {
+            if call.method == "bind" && call.args.len() == 1 {
+                params.push(call.args[0].clone());
+            }
+            for arg in call.args.iter() {
+                self.visit_expr(arg);
+            }
+            let receiver = match &*call.receiver {
+                Expr::MethodCall(receiver) => {
+                    call = receiver;
+                    continue;
+                }
+                receiver => receiver,
+            };
+            if let Expr::Call(receiver) = receiver &&
+                let Some(arg) = query_function_arg(receiver) {
+                    params.reverse();
+                    self.calls.push(QueryCall { arg, params });
+                    syn::visit::visit_expr_call(self, receiver);
+            } else {
+                self.visit_expr(receiver);
+            }
+            break;
+        }
+    }
+
+    fn visit_macro(&mut self, mac: &'ast Macro) {
+        // Macro bodies are opaque tokens, but most take expressions, e.g. `tokio::try_join!`.
+        if let Ok(args) = mac.parse_body_with(Punctuated::<Expr, Token![,]>::parse_terminated) {
+            // The query text is the first string, e.g. `query_as!(User, "SELECT ...")`, followed
+            // by the bound arguments.
+            let query = args.iter().position(|arg| string_value(arg).is_some());
+            let params = || query.map_or_else(Vec::new, |query| args.iter().skip(query + 1).cloned().collect());
+            if let Some(segment) = mac.path.segments.last() &&
+                QUERY_MACROS.iter().any(|name| segment.ident == name) &&
+                let Some(query) = args.iter().find_map(string_value) {
+                    self.calls.push(QueryCall { arg: QueryArg::Literal(query), params: params() });
+            }
+            // SQLx reports missing files itself, and tracks them for recompilation.
+            if let Some(segment) = mac.path.segments.last() &&
+                QUERY_FILE_MACROS.iter().any(|name| segment.ident == name) &&
+                let Some(path) = args.iter().find_map(string_value) &&
+                let Some(query) = read_query_file(&path) {
+                    self.calls.push(QueryCall { arg: QueryArg::Literal(query), params: params() });
+            }
+            for arg in args.iter() {
+                self.visit_expr(arg);
+            }
+        }
+        syn::visit::visit_macro(self, mac);
+    }
+}
+
+/// Looks for a binding of a specific name.
+struct BindingVisitor<'a> {
+    name: &'a str,
+    found: bool,
+}
+
+impl<'ast> Visit<'ast> for BindingVisitor<'_> {
+    fn visit_pat_ident(&mut self, pat_ident: &'ast syn::PatIdent) {
+        self.found |= pat_ident.ident == self.name;
+        syn::visit::visit_pat_ident(self, pat_ident);
+    }
+
+    fn visit_item_const(&mut self, item: &'ast syn::ItemConst) {
+        self.found |= item.ident == self.name;
+        syn::visit::visit_item_const(self, item);
+    }
+
+    fn visit_item_static(&mut self, item: &'ast syn::ItemStatic) {
+        self.found |= item.ident == self.name;
+        syn::visit::visit_item_static(self, item);
+    }
+}
+
+/// Looks for a call of a function with a specific name.
+struct CallVisitor<'a> {
+    name: &'a str,
+    found: bool,
+}
+
+impl<'ast> Visit<'ast> for CallVisitor<'_> {
+    fn visit_expr_call(&mut self, call: &'ast ExprCall) {
+        if let Expr::Path(func) = &*call.func &&
+            let Some(segment) = func.path.segments.last() {
+                self.found |= segment.ident == self.name;
+        }
+        syn::visit::visit_expr_call(self, call);
+    }
+
+    fn visit_macro(&mut self, mac: &'ast Macro) {
+        if let Ok(args) = mac.parse_body_with(Punctuated::<Expr, Token![,]>::parse_terminated) {
+            for arg in args.iter() {
+                self.visit_expr(arg);
+            }
+        }
+        syn::visit::visit_macro(self, mac);
+    }
+}
+
+/// Extracts the query text argument of a call, if it is an SQLx query function call.
+fn query_function_arg(call: &ExprCall) -> Option<QueryArg> {
+    let Expr::Path(func) = &*call.func else {
+        return None;
+    };
+    let segment = func.path.segments.last()?;
+    if !QUERY_FUNCTIONS.iter().any(|name| segment.ident == name) {
+        return None;
+    }
+    call.args.first().and_then(query_arg)
+}
+
+/// Extracts the query text argument of a query function call.
+fn query_arg(expr: &Expr) -> Option<QueryArg> {
+    match expr {
+        Expr::Path(path) => path.path.get_ident().map(|ident| QueryArg::Binding(ident.to_string())),
+        Expr::Reference(reference) => query_arg(&reference.expr),
+        Expr::Paren(paren) => query_arg(&paren.expr),
+        Expr::Group(group) => query_arg(&group.expr),
+        _ => string_value(expr).map(QueryArg::Literal),
+    }
+}
+
+/// Evaluates an expression to a string, if it is a string literal or `concat!` of literals.
+fn string_value(expr: &Expr) -> Option<LitStr> {
+    match expr {
+        Expr::Lit(expr_lit) => match &expr_lit.lit {
+            Lit::Str(lit_str) => Some(lit_str.clone()),
+            _ => None,
+        },
+        Expr::Macro(ExprMacro { mac, .. }) if mac.path.is_ident("concat") => concat_value(mac),
+        Expr::Reference(reference) => string_value(&reference.expr),
+        Expr::Paren(paren) => string_value(&paren.expr),
+        Expr::Group(group) => string_value(&group.expr),
+        _ => None,
+    }
+}
+
+/// Reads the query text from a file, relative to the crate's manifest directory like SQLx does.
+fn read_query_file(path: &LitStr) -> Option<LitStr> {
+    let manifest_dir = env::var("CARGO_MANIFEST_DIR").ok()?;
+    let query = fs::read_to_string(Path::new(&manifest_dir).join(path.value())).ok()?;
+    Some(LitStr::new(&query, path.span()))
+}
+
+/// Evaluates a `concat!` invocation of literals.
+fn concat_value(mac: &Macro) -> Option<LitStr> {
+    let args = mac
+        .parse_body_with(Punctuated::<Expr, Token![,]>::parse_terminated)
+        .ok()?;
+    let mut value = String::new();
+    for arg in args.iter() {
+        match arg {
+            Expr::Lit(expr_lit) => match &expr_lit.lit {
+                Lit::Str(lit) => value.push_str(&lit.value()),
+                Lit::Char(lit) => value.push(lit.value()),
+                Lit::Int(lit) => value.push_str(lit.base10_digits()),
+                Lit::Float(lit) => value.push_str(lit.base10_digits()),
+                Lit::Bool(lit) => value.push_str(if lit.value { "true" } else { "false" }),
+                _ => return None,
+            },
+            Expr::Macro(ExprMacro { mac, .. }) if mac.path.is_ident("concat") => {
+                value.push_str(&concat_value(mac)?.value());
+            }
+            _ => return None,
+        }
+    }
+    Some(LitStr::new(&value, mac.path.segments[0].ident.span()))
+}
+
+#[cfg(test)]
+mod tests {
+    use quote::ToTokens;
+    use syn::parse_quote;
+
+    use super::*;
+
+    fn query(block: &syn::Block) -> Option<String> {
+        find_query(block, "query").map(|query| query.value())
+    }
+
+    fn params(block: &syn::Block, binding: &str) -> Vec<String> {
+        find_params(block, binding).iter().map(|param| param.to_token_stream().to_string()).collect()
+    }
+
+    #[test]
+    fn find_query_inline() {
+        let block = parse_quote! {{ sqlx::query("DELETE FROM users").execute(db).await }};
+        assert_eq!(query(&block).as_deref(), Some("DELETE FROM users"));
+        let block = parse_quote! {{ sqlx::query_as::<_, User>("SELECT name FROM users").fetch_all(db).await }};
+        assert_eq!(query(&block).as_deref(), Some("SELECT name FROM users"));
+        let block = parse_quote! {{ sqlx::query_as!(User, "SELECT name FROM users WHERE id = ?", id).fetch_one(db).await }};
+        assert_eq!(query(&block).as_deref(), Some("SELECT name FROM users WHERE id = ?"));
+    }
+
+    #[test]
+    fn find_query_nested() {
+        let block = parse_quote! {{
+            let count = {
+                let fetch = || async { sqlx::query_scalar("SELECT COUNT(*) FROM users").fetch_one(db).await };
+                fetch().await?
+            };
+            Ok(count)
+        }};
+        assert_eq!(query(&block).as_deref(), Some("SELECT COUNT(*) FROM users"));
+    }
+
+    #[test]
+    fn find_query_bindings() {
+        let block = parse_quote! {{
+            const FETCH_USERS: &str = "SELECT name FROM users";
+            sqlx::query_as(FETCH_USERS).fetch_all(db).await
+        }};
+        assert_eq!(query(&block).as_deref(), Some("SELECT name FROM users"));
+        let block = parse_quote! {{
+            let query = concat!("SELECT name ", "FROM users ", "LIMIT ", 10);
+            sqlx::query_as(query).fetch_all(db).await
+        }};
+        assert_eq!(query(&block).as_deref(), Some("SELECT name FROM users LIMIT 10"));
+        // Shadowing the binding with something other than a literal keeps the literal.
+        let block = parse_quote! {{
+            let query = "  SELECT name FROM users  ";
+            let query = query.trim();
+            sqlx::query_as(query).fetch_all(db).await
+        }};
+        assert_eq!(query(&block).as_deref(), Some("  SELECT name FROM users  "));
+        let block = parse_quote! {{
+            let query = format!("SELECT name FROM {table}");
+            sqlx::query_as(&query).fetch_all(db).await
+        }};
+        assert_eq!(query(&block), None);
+    }
+
+    #[test]
+    fn find_params_of_detected_query() {
+        let block = parse_quote! {{
+            sqlx::query("DELETE FROM sessions WHERE user_id = ?").bind(user_id).execute(db).await?;
+            let query = "UPDATE users SET name = ? WHERE id = ?";
+            sqlx::query(query).bind(&name).bind(user.id).execute(db).await
+        }};
+        assert_eq!(params(&block, "query"), ["& name", "user . id"]);
+        assert_eq!(params(&block, "other"), ["user_id"]);
+        let block = parse_quote! {{ sqlx::query_as!(User, "SELECT name FROM users WHERE id = ? AND active = ?", id, true).fetch_one(db).await }};
+        assert_eq!(params(&block, "query"), ["id", "true"]);
+        let block = parse_quote! {{ sqlx::query(sql).bind(id).execute(db).await }};
+        assert!(params(&block, "query").is_empty());
+    }
+
+    #[test]
+    fn find_query_file() {
+        // Paths are relative to the manifest directory, which Cargo also sets when running tests.
+        let block = parse_quote! {{
+            sqlx::query_file_as!(User, "tests/queries/fetch_user.sql", user_id, true).fetch_one(db).await
+        }};
+        assert_eq!(query(&block).as_deref(), Some("SELECT name, email\nFROM users\nWHERE id = ? AND active = ?\n"));
+        assert_eq!(params(&block, "query"), ["user_id", "true"]);
+        let block = parse_quote! {{ sqlx::query_file!("tests/queries/missing.sql", user_id).fetch_one(db).await }};
+        assert_eq!(query(&block), None);
+        assert!(params(&block, "query").is_empty());
+    }
+
+    #[test]
+    fn is_bound_items() {
+        let block = parse_quote! {{
+            let (query, other) = ("SELECT 1", ());
+            sqlx::query(query).execute(db).await
+        }};
+        assert!(is_bound(&block, "query"));
+        let block = parse_quote! {{
+            static QUERY: &str = "SELECT 1";
+            sqlx::query(QUERY).execute(db).await
+        }};
+        assert!(is_bound(&block, "QUERY"));
+        let block = parse_quote! {{ sqlx::query(QUERY).execute(db).await }};
+        assert!(!is_bound(&block, "QUERY"));
+    }
+
+    #[test]
+    fn concat_values() {
+        let concat = |expr: Expr| match expr {
+            Expr::Macro(ExprMacro { mac, .. }) => concat_value(&mac).map(|value| value.value()),
+            _ => unreachable!(),
+        };
+        assert_eq!(concat(parse_quote! { concat!("a", 'b', 1, 2.5, true, concat!("c", "d")) }).as_deref(), Some("ab12.5truecd"));
+        assert_eq!(concat(parse_quote! { concat!("SELECT * FROM ", TABLE) }), None);
+    }
+}
@@ -0,0 +1,3 @@
+SELECT name, email
+FROM users
+WHERE id = ? AND active = ?
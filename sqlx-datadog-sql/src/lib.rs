@@ -0,0 +1,638 @@
+//! SQL text processing for [`sqlx-datadog`](https://docs.rs/sqlx-datadog).
+//!
+//! This is shared between the runtime crate and the procedural macros, so query text can be
+//! processed at compile time where it is known, and at runtime otherwise. The same goes for the
+//! database system names.
+
+/// Obfuscates query text for use as a Datadog `resource`, like the Datadog agent does.
+///
+/// String, number, boolean and `NULL` literals are replaced with `?`, lists of literals or
+/// placeholders in `IN` clauses are collapsed into a single `?`, comments are removed and whitespace is collapsed into
+/// single spaces. Quoted identifiers are left untouched.
+///
+/// ```
+/// # use sqlx_datadog_sql::obfuscate;
+/// assert_eq!(
+///     obfuscate("SELECT *\n  FROM users -- active ones only\n  WHERE status = 'active' AND id IN (1, 2, 3)"),
+///     "SELECT * FROM users WHERE status = ? AND id IN (?)",
+/// );
+/// ```
+pub fn obfuscate(query: &str) -> String {
+    let mut tokens = tokenize(query);
+    strip_comments(&mut tokens);
+    collapse_in_lists(&mut tokens);
+    render(&tokens, true)
+}
+
+/// Normalizes query text for display, without obfuscating it.
+///
+/// Comments are removed and whitespace is collapsed into single spaces.
+///
+/// ```
+/// # use sqlx_datadog_sql::normalize;
+/// assert_eq!(
+///     normalize("SELECT *\n  FROM users -- active ones only\n  WHERE status = 'active'"),
+///     "SELECT * FROM users WHERE status = 'active'",
+/// );
+/// ```
+pub fn normalize(query: &str) -> String {
+    let mut tokens = tokenize(query);
+    strip_comments(&mut tokens);
+    render(&tokens, false)
+}
+
+/// Returns the operation of a query for the `db.operation` tag, e.g. `SELECT`.
+///
+/// This is the leading keyword of the query, except for upserts, which are `UPSERT`, and queries
+/// starting with a common table expression, which are `CTE`.
+///
+/// ```
+/// # use sqlx_datadog_sql::operation;
+/// assert_eq!(operation("select * from users").as_deref(), Some("SELECT"));
+/// assert_eq!(
+///     operation("INSERT INTO users (id, name) VALUES (?, ?) ON DUPLICATE KEY UPDATE name = ?").as_deref(),
+///     Some("UPSERT"),
+/// );
+/// assert_eq!(operation("WITH active AS (SELECT 1) SELECT * FROM active").as_deref(), Some("CTE"));
+/// ```
+pub fn operation(query: &str) -> Option<String> {
+    let mut tokens = tokenize(query);
+    strip_comments(&mut tokens);
+    let keyword = match tokens.iter().map(|token| &token.kind).find(|kind| **kind != TokenKind::Punct('('))? {
+        TokenKind::Word(word) => word.to_ascii_uppercase(),
+        _ => return None,
+    };
+    let upsert = keyword == "INSERT" &&
+        (contains_keywords(&tokens, &["ON", "CONFLICT"]) || contains_keywords(&tokens, &["ON", "DUPLICATE", "KEY"]));
+    Some(match keyword.as_str() {
+        "WITH" => "CTE".to_string(),
+        "MERGE" | "REPLACE" => "UPSERT".to_string(),
+        "INSERT" if upsert => "UPSERT".to_string(),
+        _ => keyword,
+    })
+}
+
+/// Returns the tables a query reads from or writes to, for the `db.sql.table` tag.
+///
+/// These are the tables following `FROM`, `JOIN`, `INTO`, `UPDATE`, `TABLE` and `COPY`, in order
+/// of appearance and without duplicates, excluding common table expressions. Quoted identifiers are
+/// unquoted.
+///
+/// ```
+/// # use sqlx_datadog_sql::tables;
+/// assert_eq!(
+///     tables("SELECT * FROM users u JOIN public.\"orders\" o ON o.user_id = u.id WHERE u.id = ?"),
+///     ["users", "public.orders"],
+/// );
+/// assert_eq!(tables("WITH active AS (SELECT * FROM users) UPDATE accounts SET active = TRUE"), ["users", "accounts"]);
+/// ```
+pub fn tables(query: &str) -> Vec<String> {
+    /// Functions which use `FROM` in their arguments, e.g. `EXTRACT(YEAR FROM created_at)`.
+    const FROM_FUNCTIONS: &[&str] = &["EXTRACT", "OVERLAY", "SUBSTRING", "TRIM"];
+
+    let mut tokens = tokenize(query);
+    strip_comments(&mut tokens);
+    // `COPY` reads from or writes to a table, and its `FROM` is followed by a file or `STDIN`.
+    let copy = tokens.first().is_some_and(|token| is_keyword(token, "COPY"));
+
+    // Common table expressions look like tables, but aren't.
+    let ctes = ctes(&tokens);
+
+    let mut tables: Vec<String> = vec![];
+    // Whether each open parenthesis holds the arguments of one of the `FROM_FUNCTIONS`.
+    let mut parens: Vec<bool> = vec![];
+    let mut i = 0;
+    while i < tokens.len() {
+        let previous = i.checked_sub(1).map(|i| &tokens[i]);
+        let token = &tokens[i];
+        i += 1;
+
+        match &token.kind {
+            TokenKind::Punct('(') => {
+                let function = previous.is_some_and(|previous| FROM_FUNCTIONS.iter().any(|f| is_keyword(previous, f)));
+                parens.push(function);
+                continue;
+            }
+            TokenKind::Punct(')') => {
+                parens.pop();
+                continue;
+            }
+            _ => {}
+        }
+
+        let from = is_keyword(token, "FROM") &&
+            parens.last() != Some(&true) &&
+            !(copy && parens.is_empty()) &&
+            !previous.is_some_and(|previous| is_keyword(previous, "DISTINCT"));
+        // `UPDATE` also appears in `FOR UPDATE`, `ON DUPLICATE KEY UPDATE` and `DO UPDATE`.
+        let update = is_keyword(token, "UPDATE") &&
+            !previous.is_some_and(|previous| ["FOR", "KEY", "DO"].iter().any(|keyword| is_keyword(previous, keyword)));
+        if !(from || update || ["JOIN", "INTO", "TABLE", "COPY"].iter().any(|keyword| is_keyword(token, keyword))) {
+            continue;
+        }
+
+        // Skip modifiers, e.g. `DROP TABLE IF EXISTS` or `FROM ONLY`.
+        while tokens.get(i).is_some_and(|token| ["IF", "NOT", "EXISTS", "ONLY"].iter().any(|keyword| is_keyword(token, keyword))) {
+            i += 1;
+        }
+
+        // `FROM` takes a list of tables, each optionally followed by an alias.
+        while let Some((table, end)) = table_name(&tokens, i) {
+            // A table name followed by parentheses after `FROM` or `JOIN` is a function call.
+            let call = (from || is_keyword(token, "JOIN")) && tokens.get(end).is_some_and(|token| token.kind == TokenKind::Punct('('));
+            if !call && !ctes.contains(&table) && !tables.contains(&table) {
+                tables.push(table);
+            }
+            i = end;
+
+            let mut next = end;
+            if tokens.get(next).is_some_and(|token| is_keyword(token, "AS")) {
+                next += 1;
+            }
+            if tokens.get(next).and_then(identifier).is_some() {
+                next += 1;
+            }
+            if !from || tokens.get(next).is_none_or(|token| token.kind != TokenKind::Punct(',')) {
+                break;
+            }
+            i = next + 1;
+        }
+    }
+
+    tables
+}
+
+/// Returns the names of the common table expressions defined in `WITH` clauses, including nested
+/// ones.
+fn ctes(tokens: &[Token]) -> Vec<String> {
+    let mut ctes = vec![];
+    for start in 0..tokens.len() {
+        if !is_keyword(&tokens[start], "WITH") {
+            continue;
+        }
+        let mut i = start + 1;
+        if tokens.get(i).is_some_and(|token| is_keyword(token, "RECURSIVE")) {
+            i += 1;
+        }
+        // Each is `name [(columns)] AS [[NOT] MATERIALIZED] (query)`, separated by commas.
+        while let Some(name) = tokens.get(i).and_then(identifier) {
+            i = skip_parens(tokens, i + 1);
+            if !tokens.get(i).is_some_and(|token| is_keyword(token, "AS")) {
+                break;
+            }
+            ctes.push(name);
+            i += 1;
+            while tokens.get(i).is_some_and(|token| is_keyword(token, "NOT") || is_keyword(token, "MATERIALIZED")) {
+                i += 1;
+            }
+            i = skip_parens(tokens, i);
+            if tokens.get(i).is_none_or(|token| token.kind != TokenKind::Punct(',')) {
+                break;
+            }
+            i += 1;
+        }
+    }
+    ctes
+}
+
+/// Returns the index after the parenthesized tokens starting at `start`, or `start` if there are
+/// none there.
+fn skip_parens(tokens: &[Token], start: usize) -> usize {
+    if tokens.get(start).is_none_or(|token| token.kind != TokenKind::Punct('(')) {
+        return start;
+    }
+    let mut depth = 0;
+    for (i, token) in tokens.iter().enumerate().skip(start) {
+        match token.kind {
+            TokenKind::Punct('(') => depth += 1,
+            TokenKind::Punct(')') => depth -= 1,
+            _ => {}
+        }
+        if depth == 0 {
+            return i + 1;
+        }
+    }
+    tokens.len()
+}
+
+/// Returns the possibly qualified table name starting at `start`, and the index after it.
+fn table_name(tokens: &[Token], start: usize) -> Option<(String, usize)> {
+    let mut name = identifier(tokens.get(start)?)?;
+    let mut i = start + 1;
+    while tokens.get(i).is_some_and(|token| token.kind == TokenKind::Punct('.')) &&
+        let Some(part) = tokens.get(i + 1).and_then(identifier) {
+            name.push('.');
+            name.push_str(&part);
+            i += 2;
+    }
+    Some((name, i))
+}
+
+/// Returns the name of an unquoted or quoted identifier, without its quotes.
+///
+/// Unterminated and empty quoted identifiers are not names.
+fn identifier(token: &Token) -> Option<String> {
+    match &token.kind {
+        TokenKind::Word(word) => Some(word.clone()),
+        TokenKind::Quoted(quoted) => {
+            let quote = quoted.chars().next()?;
+            let name = quoted.strip_prefix(quote)?.strip_suffix(quote)?;
+            let doubled = format!("{quote}{quote}");
+            (!name.is_empty()).then(|| name.replace(&doubled, &quote.to_string()))
+        }
+        _ => None,
+    }
+}
+
+/// Checks whether a token is the given keyword, ignoring case.
+fn is_keyword(token: &Token, keyword: &str) -> bool {
+    matches!(&token.kind, TokenKind::Word(word) if word.eq_ignore_ascii_case(keyword))
+}
+
+/// Checks whether the tokens contain a sequence of keywords.
+fn contains_keywords(tokens: &[Token], keywords: &[&str]) -> bool {
+    tokens
+        .windows(keywords.len())
+        .any(|window| window.iter().zip(keywords).all(|(token, keyword)| is_keyword(token, keyword)))
+}
+
+/// Returns the Datadog `db.system` for an SQLx URL scheme, including aliases like `postgres`.
+///
+/// ```
+/// # use sqlx_datadog_sql::db_system;
+/// assert_eq!(db_system("postgres"), Some("postgresql"));
+/// assert_eq!(db_system("mariadb"), Some("mariadb"));
+/// assert_eq!(db_system("oracle"), None);
+/// ```
+pub fn db_system(scheme: &str) -> Option<&'static str> {
+    match scheme.to_ascii_lowercase().as_str() {
+        "mariadb" => Some("mariadb"),
+        "mysql" => Some("mysql"),
+        "postgres" | "postgresql" => Some("postgresql"),
+        "sqlite" => Some("sqlite"),
+        _ => None,
+    }
+}
+
+/// A lexical token of query text.
+#[derive(Debug, Clone, PartialEq)]
+enum TokenKind {
+    /// A keyword or unquoted identifier.
+    Word(String),
+    /// A quoted identifier, kept verbatim including its quotes.
+    Quoted(String),
+    /// A string, number, boolean or `NULL` literal, which is obfuscated.
+    Literal(String),
+    /// A bind parameter placeholder, e.g. `?` or `$1`.
+    Placeholder(String),
+    /// A comment, kept verbatim including its delimiters.
+    Comment(String),
+    /// Any other character, e.g. operators and parentheses.
+    Punct(char),
+}
+
+/// A token and whether it was preceded by whitespace.
+#[derive(Debug, Clone, PartialEq)]
+struct Token {
+    kind: TokenKind,
+    space_before: bool,
+}
+
+/// Splits query text into tokens, dropping whitespace.
+fn tokenize(query: &str) -> Vec<Token> {
+    let chars: Vec<char> = query.chars().collect();
+    let mut tokens: Vec<Token> = vec![];
+    let mut space_before = false;
+    let mut i = 0;
+
+    while i < chars.len() {
+        let c = chars[i];
+        let next = chars.get(i + 1).copied();
+        let start = i;
+
+        let kind = if c.is_whitespace() {
+            space_before = true;
+            i += 1;
+            continue;
+        } else if c == '-' && next == Some('-') {
+            while i < chars.len() && chars[i] != '\n' {
+                i += 1;
+            }
+            TokenKind::Comment(chars[start..i].iter().collect())
+        } else if c == '/' && next == Some('*') {
+            i += 2;
+            while i < chars.len() && !(chars[i] == '*' && chars.get(i + 1) == Some(&'/')) {
+                i += 1;
+            }
+            i = (i + 2).min(chars.len());
+            TokenKind::Comment(chars[start..i].iter().collect())
+        } else if c == '\'' {
+            i = skip_quoted(&chars, i, '\'', true);
+            TokenKind::Literal(chars[start..i].iter().collect())
+        } else if c == '"' || c == '`' {
+            i = skip_quoted(&chars, i, c, false);
+            TokenKind::Quoted(chars[start..i].iter().collect())
+        } else if c == '$' && next.is_some_and(|c| c.is_ascii_digit()) {
+            i += 1;
+            while i < chars.len() && chars[i].is_ascii_digit() {
+                i += 1;
+            }
+            TokenKind::Placeholder(chars[start..i].iter().collect())
+        } else if c == '$' && let Some(end) = skip_dollar_quoted(&chars, i) {
+            i = end;
+            TokenKind::Literal(chars[start..i].iter().collect())
+        } else if c == '?' {
+            i += 1;
+            TokenKind::Placeholder("?".to_string())
+        } else if c.is_ascii_digit() || (matches!(c, '-' | '.') && starts_number(&chars[i + 1..]) && starts_operand(&tokens)) {
+            i = skip_number(&chars, i + 1);
+            TokenKind::Literal(chars[start..i].iter().collect())
+        } else if c.is_alphabetic() || c == '_' {
+            while i < chars.len() && (chars[i].is_alphanumeric() || chars[i] == '_' || chars[i] == '$') {
+                i += 1;
+            }
+            let word: String = chars[start..i].iter().collect();
+            // Prefixed strings, e.g. `E'...'` or `X'...'`.
+            if chars.get(i) == Some(&'\'') && matches!(word.to_ascii_uppercase().as_str(), "B" | "E" | "N" | "X") {
+                i = skip_quoted(&chars, i, '\'', true);
+                TokenKind::Literal(chars[start..i].iter().collect())
+            } else if ["TRUE", "FALSE", "NULL"].iter().any(|keyword| word.eq_ignore_ascii_case(keyword)) {
+                TokenKind::Literal(word)
+            } else {
+                TokenKind::Word(word)
+            }
+        } else {
+            i += 1;
+            TokenKind::Punct(c)
+        };
+
+        tokens.push(Token { kind, space_before });
+        space_before = false;
+    }
+
+    tokens
+}
+
+/// Returns the index after a quoted string or identifier starting at `start`.
+///
+/// Doubled quotes are treated as escaped quotes, and so are backslash-escaped ones if
+/// `backslash_escapes` is set.
+fn skip_quoted(chars: &[char], start: usize, quote: char, backslash_escapes: bool) -> usize {
+    let mut i = start + 1;
+    while i < chars.len() {
+        let escaped = backslash_escapes && chars[i] == '\\';
+        let doubled = chars[i] == quote && chars.get(i + 1) == Some(&quote);
+        if escaped || doubled {
+            i += 2;
+        } else if chars[i] == quote {
+            return i + 1;
+        } else {
+            i += 1;
+        }
+    }
+    chars.len()
+}
+
+/// Returns the index after a Postgres dollar-quoted string starting at `start`, if there is one.
+fn skip_dollar_quoted(chars: &[char], start: usize) -> Option<usize> {
+    let mut i = start + 1;
+    while i < chars.len() && (chars[i].is_alphanumeric() || chars[i] == '_') {
+        i += 1;
+    }
+    if chars.get(i) != Some(&'$') {
+        return None;
+    }
+    let tag = &chars[start..=i];
+    i += 1;
+    while i < chars.len() {
+        if chars[i..].starts_with(tag) {
+            return Some(i + tag.len());
+        }
+        i += 1;
+    }
+    Some(chars.len())
+}
+
+/// Returns the index after a number whose first character is at `start - 1`.
+fn skip_number(chars: &[char], start: usize) -> usize {
+    let mut i = start;
+    while i < chars.len() {
+        let c = chars[i];
+        let exponent_sign = (c == '+' || c == '-') && matches!(chars[i - 1], 'e' | 'E');
+        if c.is_ascii_alphanumeric() || c == '.' || exponent_sign {
+            i += 1;
+        } else {
+            break;
+        }
+    }
+    i
+}
+
+/// Checks whether a number without a sign starts at the beginning of `chars`, e.g. `1` or `.5`.
+fn starts_number(chars: &[char]) -> bool {
+    match chars {
+        [c, ..] if c.is_ascii_digit() => true,
+        ['.', c, ..] => c.is_ascii_digit(),
+        _ => false,
+    }
+}
+
+/// Checks whether the next token starts an operand, to tell negative numbers from subtraction.
+fn starts_operand(tokens: &[Token]) -> bool {
+    /// Keywords which are followed by an operand.
+    const KEYWORDS: &[&str] = &[
+        "AND", "BETWEEN", "ELSE", "IN", "LIKE", "LIMIT", "NOT", "OFFSET", "OR", "SELECT", "SET",
+        "THEN", "VALUES", "WHEN", "WHERE",
+    ];
+
+    match tokens.last().map(|token| &token.kind) {
+        None | Some(TokenKind::Comment(_)) => true,
+        Some(TokenKind::Punct(c)) => *c != ')',
+        Some(TokenKind::Word(word)) => KEYWORDS.iter().any(|keyword| word.eq_ignore_ascii_case(keyword)),
+        _ => false,
+    }
+}
+
+/// Removes comments, which separate the surrounding tokens like whitespace does.
+fn strip_comments(tokens: &mut Vec<Token>) {
+    let mut after_comment = false;
+    tokens.retain_mut(|token| {
+        if let TokenKind::Comment(_) = token.kind {
+            after_comment = true;
+            return false;
+        }
+        token.space_before |= after_comment;
+        after_comment = false;
+        true
+    });
+}
+
+/// Collapses lists of literals and placeholders in `IN` clauses into a single `?`.
+fn collapse_in_lists(tokens: &mut Vec<Token>) {
+    let mut i = 0;
+    while i < tokens.len() {
+        let is_in = matches!(&tokens[i].kind, TokenKind::Word(word) if word.eq_ignore_ascii_case("IN"));
+        if is_in && tokens.get(i + 1).is_some_and(|token| token.kind == TokenKind::Punct('(')) {
+            let start = i + 2;
+            let mut end = start;
+            let mut expect_value = true;
+            while let Some(token) = tokens.get(end) {
+                match (&token.kind, expect_value) {
+                    (TokenKind::Literal(_) | TokenKind::Placeholder(_), true) => expect_value = false,
+                    (TokenKind::Punct(','), false) => expect_value = true,
+                    _ => break,
+                }
+                end += 1;
+            }
+            if end > start &&
+                !expect_value &&
+                tokens.get(end).is_some_and(|token| token.kind == TokenKind::Punct(')')) {
+                    let literal = Token { kind: TokenKind::Literal("?".to_string()), space_before: false };
+                    tokens.splice(start..end, [literal]);
+                    tokens[start + 1].space_before = false;
+            }
+        }
+        i += 1;
+    }
+}
+
+/// Renders tokens back into query text, separating them by single spaces where there was
+/// whitespace before, and replacing literals with `?` if `obfuscate` is set.
+fn render(tokens: &[Token], obfuscate: bool) -> String {
+    let mut query = String::new();
+    for token in tokens {
+        if token.space_before && !query.is_empty() {
+            query.push(' ');
+        }
+        match &token.kind {
+            TokenKind::Literal(_) if obfuscate => query.push('?'),
+            TokenKind::Word(text) |
+            TokenKind::Quoted(text) |
+            TokenKind::Literal(text) |
+            TokenKind::Placeholder(text) |
+            TokenKind::Comment(text) => query.push_str(text),
+            TokenKind::Punct(c) => query.push(*c),
+        }
+    }
+    query
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn obfuscate_escaped_strings() {
+        assert_eq!(obfuscate("SELECT * FROM users WHERE name = 'O''Brien'"), "SELECT * FROM users WHERE name = ?");
+        assert_eq!(obfuscate("SELECT * FROM users WHERE name = 'O\\'Brien'"), "SELECT * FROM users WHERE name = ?");
+        assert_eq!(obfuscate("SELECT * FROM users WHERE name = E'\\n' AND data = X'ff'"), "SELECT * FROM users WHERE name = ? AND data = ?");
+        assert_eq!(obfuscate("SELECT \"col\"\"name\" FROM `users`"), "SELECT \"col\"\"name\" FROM `users`");
+    }
+
+    #[test]
+    fn obfuscate_unterminated() {
+        assert_eq!(obfuscate("SELECT * FROM users WHERE name = 'abc"), "SELECT * FROM users WHERE name = ?");
+        assert_eq!(obfuscate("SELECT * FROM users WHERE name = 'abc\\"), "SELECT * FROM users WHERE name = ?");
+        assert_eq!(obfuscate("SELECT * FROM users /* unfinished"), "SELECT * FROM users");
+        assert_eq!(obfuscate("SELECT $tag$ unfinished"), "SELECT ?");
+        assert_eq!(obfuscate("SELECT 'é"), "SELECT ?");
+    }
+
+    #[test]
+    fn obfuscate_dollar_quotes_and_placeholders() {
+        assert_eq!(obfuscate("SELECT $$it's$$, $tag$a $$ b$tag$ FROM users WHERE id = $1"), "SELECT ?, ? FROM users WHERE id = $1");
+        assert_eq!(obfuscate("SELECT price$ FROM items"), "SELECT price$ FROM items");
+    }
+
+    #[test]
+    fn obfuscate_numbers() {
+        assert_eq!(obfuscate("SELECT * FROM t WHERE a = -1 AND b = 1.5e-3 AND c = 0x1F"), "SELECT * FROM t WHERE a = ? AND b = ? AND c = ?");
+        assert_eq!(obfuscate("SELECT a-1, (b)-2, c - -3 FROM t"), "SELECT a-?, (b)-?, c - ? FROM t");
+        assert_eq!(obfuscate("SELECT * FROM t2 LIMIT -1"), "SELECT * FROM t2 LIMIT ?");
+        assert_eq!(obfuscate("SELECT 1.5, .5, -.5, t.a FROM t WHERE b > .25"), "SELECT ?, ?, ?, t.a FROM t WHERE b > ?");
+    }
+
+    #[test]
+    fn obfuscate_booleans_and_null() {
+        assert_eq!(obfuscate("UPDATE users SET active = TRUE, deleted = false WHERE note IS NULL"), "UPDATE users SET active = ?, deleted = ? WHERE note IS ?");
+        assert_eq!(obfuscate("SELECT * FROM t WHERE a IN (TRUE, NULL)"), "SELECT * FROM t WHERE a IN (?)");
+        assert_eq!(normalize("SELECT * FROM t WHERE a IS NULL"), "SELECT * FROM t WHERE a IS NULL");
+    }
+
+    #[test]
+    fn obfuscate_in_lists() {
+        assert_eq!(obfuscate("SELECT * FROM t WHERE id IN (?, ?, ?)"), "SELECT * FROM t WHERE id IN (?)");
+        assert_eq!(obfuscate("SELECT * FROM t WHERE id in ( $1 , $2 )"), "SELECT * FROM t WHERE id in (?)");
+        assert_eq!(obfuscate("SELECT * FROM t WHERE id IN (1, other_id)"), "SELECT * FROM t WHERE id IN (?, other_id)");
+        assert_eq!(obfuscate("SELECT * FROM t WHERE id IN ()"), "SELECT * FROM t WHERE id IN ()");
+        assert_eq!(obfuscate("SELECT * FROM t WHERE id IN (1, 2,)"), "SELECT * FROM t WHERE id IN (?, ?,)");
+        assert_eq!(obfuscate("SELECT * FROM t WHERE id IN (SELECT id FROM u WHERE x IN (1, 2))"), "SELECT * FROM t WHERE id IN (SELECT id FROM u WHERE x IN (?))");
+        assert_eq!(obfuscate("SELECT * FROM t WHERE (a, b) IN ((1, 2), (3, 4))"), "SELECT * FROM t WHERE (a, b) IN ((?, ?), (?, ?))");
+    }
+
+    #[test]
+    fn tables_ignores_unterminated_quotes() {
+        assert!(tables("SELECT * FROM \"").is_empty());
+        assert!(tables("SELECT * FROM \"é").is_empty());
+        assert!(tables("SELECT * FROM `users").is_empty());
+        assert!(tables("WITH \" AS (SELECT 1) SELECT * FROM users").is_empty());
+    }
+
+    #[test]
+    fn tables_unquotes_identifiers() {
+        assert_eq!(tables("SELECT * FROM \"usérs\""), ["usérs"]);
+        assert_eq!(tables("SELECT * FROM \"odd\"\"name\""), ["odd\"name"]);
+        assert_eq!(tables("SELECT * FROM `shop`.`orders`"), ["shop.orders"]);
+        assert!(tables("SELECT * FROM \"\"").is_empty());
+    }
+
+    #[test]
+    fn tables_reads_from_lists() {
+        assert_eq!(tables("SELECT * FROM users u, orders AS o, items WHERE u.id = o.user_id"), ["users", "orders", "items"]);
+        assert_eq!(tables("SELECT * FROM ONLY users"), ["users"]);
+        assert_eq!(tables("SELECT * FROM generate_series(1, 10)"), Vec::<String>::new());
+    }
+
+    #[test]
+    fn tables_skips_from_in_function_arguments() {
+        assert_eq!(tables("SELECT EXTRACT(YEAR FROM created_at) FROM users"), ["users"]);
+        assert_eq!(tables("SELECT TRIM(LEADING 'x' FROM name), SUBSTRING(name FROM 2) FROM users"), ["users"]);
+        assert_eq!(tables("SELECT a IS DISTINCT FROM b FROM users"), ["users"]);
+    }
+
+    #[test]
+    fn tables_reads_subqueries_and_ctes() {
+        assert_eq!(
+            tables("SELECT * FROM users WHERE id IN (SELECT user_id FROM orders JOIN items ON items.order_id = orders.id)"),
+            ["users", "orders", "items"],
+        );
+        assert_eq!(
+            tables("WITH recent AS (SELECT * FROM orders), \"big\" AS (SELECT * FROM recent) SELECT * FROM big"),
+            ["orders"],
+        );
+        assert_eq!(
+            tables("WITH RECURSIVE tree (id, parent_id) AS MATERIALIZED (SELECT id, parent_id FROM nodes UNION ALL SELECT * FROM tree) SELECT * FROM tree"),
+            ["nodes"],
+        );
+        assert_eq!(
+            tables("SELECT * FROM (WITH recent AS (SELECT * FROM orders) SELECT * FROM recent) AS r JOIN recent_orders ON TRUE"),
+            ["orders", "recent_orders"],
+        );
+        assert_eq!(tables("CREATE TABLE archive AS (SELECT * FROM orders)"), ["archive", "orders"]);
+    }
+
+    #[test]
+    fn tables_reads_writes() {
+        assert_eq!(tables("INSERT INTO users (id) VALUES (?) ON DUPLICATE KEY UPDATE id = ?"), ["users"]);
+        assert_eq!(tables("INSERT INTO users (id) VALUES ($1) ON CONFLICT (id) DO UPDATE SET id = $1"), ["users"]);
+        assert_eq!(tables("SELECT * FROM users FOR UPDATE"), ["users"]);
+        assert_eq!(tables("DROP TABLE IF EXISTS users"), ["users"]);
+    }
+
+    #[test]
+    fn tables_reads_copy() {
+        assert_eq!(tables("COPY users FROM STDIN"), ["users"]);
+        assert_eq!(tables("COPY users (id, name) FROM '/tmp/users.csv'"), ["users"]);
+        assert_eq!(tables("COPY (SELECT * FROM users) TO STDOUT"), ["users"]);
+    }
+}
@@ -0,0 +1,89 @@
+use sqlx::{Database, Transaction, pool::PoolConnection};
+use tracing::Span;
+
+/// Records connection-level span tags for a database handle.
+///
+/// This is what `instrument_query` uses to tag spans with the database system, name and address,
+/// so it is implemented for the pools, connections and transactions of all enabled SQLx backends,
+/// as well as references to them.
+///
+/// Connections don't know how they were configured, so only pools record the database name and
+/// address, while connections and transactions record only the database system.
+///
+/// Functions that are generic over an executor can require this trait to be instrumented:
+///
+/// ```
+/// # use sqlx_datadog::{ConnectionTags, instrument_query};
+/// #
+/// #[instrument_query(skip(db))]
+/// async fn delete_user<'c, E>(db: E, user_id: i64) -> Result<(), sqlx::Error>
+/// where
+///     E: sqlx::MySqlExecutor<'c> + ConnectionTags,
+/// {
+///     let query = "DELETE FROM users WHERE id = ?";
+///     sqlx::query(query).bind(user_id).execute(db).await?;
+///     Ok(())
+/// }
+///
+/// async fn delete_users(pool: &sqlx::MySqlPool) -> Result<(), sqlx::Error> {
+///     let mut tx = pool.begin().await?;
+///     delete_user(&mut *tx, 1).await?;
+///     delete_user(&mut *tx, 2).await?;
+///     tx.commit().await
+/// }
+/// ```
+pub trait ConnectionTags {
+    /// Records the connection tags of this handle on `span`.
+    fn record_connection_tags(&self, span: &Span);
+}
+
+impl<T: ConnectionTags + ?Sized> ConnectionTags for &T {
+    fn record_connection_tags(&self, span: &Span) {
+        (**self).record_connection_tags(span);
+    }
+}
+
+impl<T: ConnectionTags + ?Sized> ConnectionTags for &mut T {
+    fn record_connection_tags(&self, span: &Span) {
+        (**self).record_connection_tags(span);
+    }
+}
+
+impl<DB: Database> ConnectionTags for PoolConnection<DB>
+where
+    DB::Connection: ConnectionTags,
+{
+    fn record_connection_tags(&self, span: &Span) {
+        (**self).record_connection_tags(span);
+    }
+}
+
+impl<DB: Database> ConnectionTags for Transaction<'_, DB>
+where
+    DB::Connection: ConnectionTags,
+{
+    fn record_connection_tags(&self, span: &Span) {
+        (**self).record_connection_tags(span);
+    }
+}
+
+/// Implements [`ConnectionTags`] for a connection, which only knows its database system.
+#[cfg(any(feature = "mysql", feature = "postgres", feature = "sqlite"))]
+macro_rules! impl_connection_tags {
+    ($connection:ty) => {
+        impl ConnectionTags for $connection {
+            fn record_connection_tags(&self, span: &Span) {
+                span.record("db.system", crate::metadata::db_system::<<Self as ::sqlx::Connection>::Database>());
+            }
+        }
+    };
+}
+
+#[cfg(feature = "mysql")]
+impl_connection_tags!(::sqlx::MySqlConnection);
+
+#[cfg(feature = "postgres")]
+impl_connection_tags!(::sqlx::PgConnection);
+
+#[cfg(feature = "sqlite")]
+impl_connection_tags!(::sqlx::SqliteConnection);
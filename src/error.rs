@@ -0,0 +1,138 @@
+use std::{
+    any::{Any, type_name},
+    fmt::{Debug, Display},
+};
+
+use sqlx::error::{DatabaseError, ErrorKind};
+use tracing::Span;
+
+/// Records Datadog error tags for `error` on `span`.
+///
+/// Sets `error.type`, `error.message` and `error.stack`. For [`sqlx::Error`], the type is the
+/// name of the variant, e.g. `RowNotFound` or `PoolTimedOut`, otherwise it is the name of the
+/// error type.
+///
+/// Errors returned by the database additionally get `db.error.code` (the SQLSTATE or
+/// backend-specific code), `db.error.constraint` and `db.error.kind`, which is one of
+/// `unique_violation`, `foreign_key_violation`, `not_null_violation`, `check_violation`,
+/// `deadlock`, `lock_timeout`, `serialization_failure` or `other`.
+///
+/// `instrument_query` records the same tags automatically for functions returning a `Result`, as
+/// long as the error implements [`Display`] and [`Debug`]. Use this for errors it doesn't see, e.g.
+/// ones that are handled within the function.
+pub fn record_error<E>(span: &Span, error: &E)
+where
+    E: Display + Debug + 'static,
+{
+    match (error as &dyn Any).downcast_ref::<sqlx::Error>() {
+        Some(error) => record_sqlx_error(span, error),
+        None => record_error_tags(span, type_name::<E>(), error),
+    }
+}
+
+/// Records the error tags of a [`sqlx::Error`], including those of database errors.
+fn record_sqlx_error(span: &Span, error: &sqlx::Error) {
+    record_error_tags(span, sqlx_error_variant(error), error);
+    if let sqlx::Error::Database(database_error) = error {
+        span.record("db.error.code", database_error.code().as_deref());
+        span.record("db.error.constraint", database_error.constraint());
+        span.record("db.error.kind", database_error_kind(&**database_error));
+    }
+}
+
+/// Records the tags every error gets.
+fn record_error_tags(span: &Span, error_type: &str, error: &(impl Display + Debug + ?Sized)) {
+    span.record("error.type", error_type);
+    span.record("error.message", error.to_string());
+    span.record("error.stack", format!("{error:?}"));
+}
+
+/// An error returned from a function using `instrument_query`, which may be of any type.
+///
+/// Calling `record_error` on `&&&ErrorTags(error)` with all three traits below in scope picks the
+/// most specific implementation, as method resolution tries fewer dereferences first. This way,
+/// errors which can't be recorded are skipped instead of failing to compile.
+pub struct ErrorTags<'a, E: ?Sized>(pub &'a E);
+
+/// Records a [`sqlx::Error`], see [`ErrorTags`].
+pub trait RecordSqlxError {
+    fn record_error(&self, span: &Span);
+}
+
+impl RecordSqlxError for &&ErrorTags<'_, sqlx::Error> {
+    fn record_error(&self, span: &Span) {
+        record_sqlx_error(span, self.0);
+    }
+}
+
+/// Records any other error that can be formatted, including borrowed ones, see [`ErrorTags`].
+pub trait RecordDisplayError {
+    fn record_error(&self, span: &Span);
+}
+
+impl<E: Display + Debug + ?Sized> RecordDisplayError for &ErrorTags<'_, E> {
+    fn record_error(&self, span: &Span) {
+        record_error_tags(span, type_name::<E>(), self.0);
+    }
+}
+
+/// Skips errors that can't be formatted, see [`ErrorTags`].
+pub trait SkipError {
+    fn record_error(&self, span: &Span);
+}
+
+impl<E: ?Sized> SkipError for ErrorTags<'_, E> {
+    fn record_error(&self, _span: &Span) {}
+}
+
+/// Classifies a database error for faceting, beyond what [`ErrorKind`] distinguishes.
+fn database_error_kind(error: &dyn DatabaseError) -> &'static str {
+    match error.kind() {
+        ErrorKind::UniqueViolation => return "unique_violation",
+        ErrorKind::ForeignKeyViolation => return "foreign_key_violation",
+        ErrorKind::NotNullViolation => return "not_null_violation",
+        ErrorKind::CheckViolation => return "check_violation",
+        _ => {}
+    }
+
+    // MySQL reports deadlocks with the same SQLSTATE as serialization failures, but the error
+    // number tells them apart.
+    #[cfg(feature = "mysql")]
+    if let Some(error) = error.try_downcast_ref::<sqlx::mysql::MySqlDatabaseError>() {
+        match error.number() {
+            1213 => return "deadlock",
+            1205 => return "lock_timeout",
+            _ => {}
+        }
+    }
+
+    match error.code().as_deref() {
+        Some("40P01") => "deadlock",
+        Some("55P03") => "lock_timeout",
+        Some("40001") => "serialization_failure",
+        _ => "other",
+    }
+}
+
+/// Returns the name of the variant of a [`sqlx::Error`].
+fn sqlx_error_variant(error: &sqlx::Error) -> &'static str {
+    match error {
+        sqlx::Error::Configuration(_) => "Configuration",
+        sqlx::Error::Database(_) => "Database",
+        sqlx::Error::Io(_) => "Io",
+        sqlx::Error::Tls(_) => "Tls",
+        sqlx::Error::Protocol(_) => "Protocol",
+        sqlx::Error::RowNotFound => "RowNotFound",
+        sqlx::Error::TypeNotFound { .. } => "TypeNotFound",
+        sqlx::Error::ColumnIndexOutOfBounds { .. } => "ColumnIndexOutOfBounds",
+        sqlx::Error::ColumnNotFound(_) => "ColumnNotFound",
+        sqlx::Error::ColumnDecode { .. } => "ColumnDecode",
+        sqlx::Error::Encode(_) => "Encode",
+        sqlx::Error::Decode(_) => "Decode",
+        sqlx::Error::AnyDriverError(_) => "AnyDriverError",
+        sqlx::Error::PoolTimedOut => "PoolTimedOut",
+        sqlx::Error::PoolClosed => "PoolClosed",
+        sqlx::Error::WorkerCrashed => "WorkerCrashed",
+        _ => "Unknown",
+    }
+}
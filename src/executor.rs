@@ -0,0 +1,199 @@
+use std::{
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use futures_core::{Stream, future::BoxFuture, stream::BoxStream};
+use sqlx::{Database, Describe, Either, Error, Execute, Executor};
+use tracing::{Instrument, Span, field::Empty};
+
+use crate::{ConnectionTags, RowCount, record_error, record_query, service::record_default_peer_service};
+
+/// Wraps an executor to record a span for every query it executes, with the same tags as
+/// `instrument_query`.
+///
+/// This works with anything that can execute queries and records connection tags, like
+/// connections and transactions, without annotating every function:
+///
+/// ```
+/// # use sqlx_datadog::InstrumentedExecutor;
+/// #
+/// async fn delete_user(tx: &mut sqlx::Transaction<'_, sqlx::MySql>, user_id: i64) -> Result<(), sqlx::Error> {
+///     sqlx::query("DELETE FROM users WHERE id = ?")
+///         .bind(user_id)
+///         .execute(InstrumentedExecutor::new(&mut **tx))
+///         .await?;
+///     Ok(())
+/// }
+/// ```
+///
+/// The query text is taken from the executed query at runtime, and `db.row_count` is the number
+/// of rows returned, or affected if there are none.
+#[derive(Debug)]
+pub struct InstrumentedExecutor<E> {
+    inner: E,
+}
+
+impl<E> InstrumentedExecutor<E> {
+    /// Wraps `executor`.
+    pub fn new(executor: E) -> Self {
+        Self { inner: executor }
+    }
+
+    /// Returns the wrapped executor.
+    pub fn into_inner(self) -> E {
+        self.inner
+    }
+}
+
+impl<'c, E> Executor<'c> for InstrumentedExecutor<E>
+where
+    E: Executor<'c> + ConnectionTags,
+    <E::Database as Database>::QueryResult: RowCount,
+{
+    type Database = E::Database;
+
+    fn fetch_many<'e, 'q: 'e, Q>(
+        self,
+        query: Q,
+    ) -> BoxStream<
+        'e,
+        Result<Either<<Self::Database as Database>::QueryResult, <Self::Database as Database>::Row>, Error>,
+    >
+    where
+        'c: 'e,
+        Q: 'q + Execute<'q, Self::Database>,
+    {
+        let span = statement_span(&self.inner, query.sql());
+        let stream = span.in_scope(|| self.inner.fetch_many(query));
+        Box::pin(InstrumentedStream::new(stream, span))
+    }
+
+    fn fetch_optional<'e, 'q: 'e, Q>(
+        self,
+        query: Q,
+    ) -> BoxFuture<'e, Result<Option<<Self::Database as Database>::Row>, Error>>
+    where
+        'c: 'e,
+        Q: 'q + Execute<'q, Self::Database>,
+    {
+        let span = statement_span(&self.inner, query.sql());
+        let future = span.in_scope(|| self.inner.fetch_optional(query));
+        Box::pin(instrument_future(future, span))
+    }
+
+    fn prepare_with<'e, 'q: 'e>(
+        self,
+        sql: &'q str,
+        parameters: &'e [<Self::Database as Database>::TypeInfo],
+    ) -> BoxFuture<'e, Result<<Self::Database as Database>::Statement<'q>, Error>>
+    where
+        'c: 'e,
+    {
+        self.inner.prepare_with(sql, parameters)
+    }
+
+    fn describe<'e, 'q: 'e>(self, sql: &'q str) -> BoxFuture<'e, Result<Describe<Self::Database>, Error>>
+    where
+        'c: 'e,
+    {
+        self.inner.describe(sql)
+    }
+}
+
+/// Creates the span for executing `query` on `connection`.
+pub(crate) fn statement_span(connection: &impl ConnectionTags, query: &str) -> Span {
+    let span = query_span();
+    connection.record_connection_tags(&span);
+    record_default_peer_service(&span);
+    record_query(&span, query);
+    span
+}
+
+/// Creates a query span with the same fields as `instrument_query`, which `tests/sqlite.rs` checks.
+fn query_span() -> Span {
+    tracing::info_span!(
+        "sqlx.query",
+        span.kind = "client",
+        span.type = "sql",
+        component = "sqlx",
+        operation = "sqlx.query",
+        resource = Empty,
+        service = Empty,
+        peer.service = Empty,
+        peer.db.name = Empty,
+        peer.hostname = Empty,
+        out.host = Empty,
+        out.port = Empty,
+        db.system = Empty,
+        db.instance = Empty,
+        db.name = Empty,
+        db.statement = Empty,
+        db.operation = Empty,
+        db.sql.table = Empty,
+        error.type = Empty,
+        error.message = Empty,
+        error.stack = Empty,
+        db.error.code = Empty,
+        db.error.constraint = Empty,
+        db.error.kind = Empty,
+        db.row_count = Empty,
+        db.pool.wait_ms = Empty,
+        _dd.dbm_trace_injected = Empty,
+    )
+}
+
+/// Runs a query future in its span, recording errors and the row count on it.
+pub(crate) async fn instrument_future<T, F>(future: F, span: Span) -> Result<T, Error>
+where
+    T: RowCount,
+    F: Future<Output = Result<T, Error>>,
+{
+    let result = future.instrument(span.clone()).await;
+    match &result {
+        Ok(value) => {
+            span.record("db.row_count", value.row_count());
+        }
+        Err(error) => record_error(&span, error),
+    }
+    result
+}
+
+/// Polls a query stream in its span, recording errors and the row count on it.
+pub(crate) struct InstrumentedStream<'e, T> {
+    stream: BoxStream<'e, Result<T, Error>>,
+    span: Span,
+    rows: u64,
+    rows_affected: u64,
+}
+
+impl<'e, T> InstrumentedStream<'e, T> {
+    /// Wraps `stream`, which is polled in `span`.
+    pub(crate) fn new(stream: BoxStream<'e, Result<T, Error>>, span: Span) -> Self {
+        Self { stream, span, rows: 0, rows_affected: 0 }
+    }
+}
+
+impl<QueryResult: RowCount, Row> Stream for InstrumentedStream<'_, Either<QueryResult, Row>> {
+    type Item = Result<Either<QueryResult, Row>, Error>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = &mut *self;
+        let poll = this.span.in_scope(|| this.stream.as_mut().poll_next(cx));
+        match &poll {
+            Poll::Ready(Some(Ok(Either::Left(result)))) => this.rows_affected += result.row_count(),
+            Poll::Ready(Some(Ok(Either::Right(_)))) => this.rows += 1,
+            Poll::Ready(Some(Err(error))) => record_error(&this.span, error),
+            Poll::Ready(None) => {
+                let row_count = if this.rows > 0 { this.rows } else { this.rows_affected };
+                this.span.record("db.row_count", row_count);
+            }
+            Poll::Pending => {}
+        }
+        poll
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.stream.size_hint()
+    }
+}
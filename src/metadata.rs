@@ -0,0 +1,180 @@
+// Without a backend, there are no pools to compute metadata for.
+#![cfg_attr(not(any(feature = "mysql", feature = "postgres", feature = "sqlite")), allow(dead_code))]
+
+use std::{
+    any::Any,
+    collections::HashMap,
+    sync::{Arc, OnceLock, RwLock, Weak},
+};
+
+use sqlx::{Database, Pool};
+use tracing::Span;
+
+use self::sealed::Metadata as _;
+use crate::ConnectionTags;
+
+/// Returns the `db.system` of a backend, which is the same for all its URL schemes.
+///
+/// MariaDB uses the MySQL backend, so it can't be told apart and needs to be set explicitly.
+pub(crate) fn db_system<DB: Database>() -> &'static str {
+    sqlx_datadog_sql::db_system(DB::URL_SCHEMES[0]).unwrap_or(DB::NAME)
+}
+
+/// Implemented for the pools of all enabled SQLx backends, which know their database system, name
+/// and address, e.g. to tag the metrics of a [`PoolMetricsExporter`](crate::PoolMetricsExporter).
+///
+/// This trait is sealed, it can't be implemented outside of this crate.
+pub trait PoolMetadata: sealed::Metadata {}
+
+impl<T: sealed::Metadata> PoolMetadata for T {}
+
+pub(crate) mod sealed {
+    use std::sync::Arc;
+
+    use super::ConnectionMetadata;
+
+    /// Provides the connection tags of a pool, for everything that tags more than spans with them.
+    pub trait Metadata {
+        /// Returns the connection tags of this pool.
+        fn metadata(&self) -> Arc<ConnectionMetadata>;
+    }
+}
+
+impl<DB: Database> ConnectionTags for Pool<DB>
+where
+    Self: PoolMetadata,
+{
+    fn record_connection_tags(&self, span: &Span) {
+        self.metadata().record(span);
+    }
+}
+
+/// Implements [`PoolMetadata`] for the pools of backends that connect over the network.
+#[cfg(any(feature = "mysql", feature = "postgres"))]
+macro_rules! impl_networked_pool_metadata {
+    ($db:ty) => {
+        impl sealed::Metadata for Pool<$db> {
+            fn metadata(&self) -> Arc<ConnectionMetadata> {
+                ConnectionMetadata::cached(self.connect_options(), |options| ConnectionMetadata {
+                    system: db_system::<$db>(),
+                    name: options.get_database().map(ToString::to_string),
+                    host: Some(options.get_host().to_string()),
+                    port: Some(options.get_port()),
+                })
+            }
+        }
+    };
+}
+
+#[cfg(feature = "mysql")]
+impl_networked_pool_metadata!(::sqlx::MySql);
+
+#[cfg(feature = "postgres")]
+impl_networked_pool_metadata!(::sqlx::Postgres);
+
+#[cfg(feature = "sqlite")]
+impl sealed::Metadata for Pool<::sqlx::Sqlite> {
+    fn metadata(&self) -> Arc<ConnectionMetadata> {
+        ConnectionMetadata::cached(self.connect_options(), |options| {
+            // SQLite has no server to connect to, so the file is all there is to identify it by.
+            ConnectionMetadata {
+                system: db_system::<::sqlx::Sqlite>(),
+                name: Some(options.get_filename().to_string_lossy().into_owned()),
+                host: None,
+                port: None,
+            }
+        })
+    }
+}
+
+/// The connection tags of a pool, which are derived from its connect options.
+#[derive(Debug)]
+pub struct ConnectionMetadata {
+    pub(crate) system: &'static str,
+    pub(crate) name: Option<String>,
+    pub(crate) host: Option<String>,
+    pub(crate) port: Option<u16>,
+}
+
+/// Metadata by connect options address, which the weak reference keeps from being reused.
+type MetadataCache = HashMap<usize, (Weak<dyn Any + Send + Sync>, Arc<ConnectionMetadata>)>;
+
+impl ConnectionMetadata {
+    /// Returns the metadata for a pool's connect options, computing it only once per pool.
+    ///
+    /// Pools share their connect options between clones, so the options' address identifies the
+    /// pool, unless its options are replaced, which then just computes the metadata anew.
+    fn cached<O>(options: Arc<O>, compute: impl FnOnce(&O) -> Self) -> Arc<Self>
+    where
+        O: Any + Send + Sync,
+    {
+        static CACHE: OnceLock<RwLock<MetadataCache>> = OnceLock::new();
+
+        let cache = CACHE.get_or_init(Default::default);
+        let key = Arc::as_ptr(&options) as usize;
+        if let Some((_, metadata)) = cache.read().expect("metadata cache poisoned").get(&key) {
+            return metadata.clone();
+        }
+
+        let metadata = Arc::new(compute(&options));
+        let options: Arc<dyn Any + Send + Sync> = options;
+        let mut cache = cache.write().expect("metadata cache poisoned");
+        cache.retain(|_, (options, _)| options.strong_count() > 0);
+        cache.insert(key, (Arc::downgrade(&options), metadata.clone()));
+        metadata
+    }
+
+    /// Records the metadata on `span`.
+    fn record(&self, span: &Span) {
+        if let Some(host) = &self.host {
+            span.record("peer.hostname", host.as_str());
+            span.record("out.host", host.as_str());
+        }
+        span.record("out.port", self.port);
+        span.record("db.instance", self.name.as_deref());
+        span.record("db.name", self.name.as_deref());
+        span.record("peer.db.name", self.name.as_deref());
+        span.record("db.system", self.system);
+    }
+}
+
+#[cfg(all(test, feature = "mysql"))]
+mod tests {
+    use sqlx::{MySql, mysql::MySqlConnectOptions, pool::PoolOptions};
+
+    use super::*;
+
+    /// Opens a pool without connecting, or needing a runtime.
+    fn lazy_pool(database: &str) -> Pool<MySql> {
+        PoolOptions::new()
+            .max_lifetime(None)
+            .idle_timeout(None)
+            .connect_lazy_with(MySqlConnectOptions::new().host("db.example.com").database(database))
+    }
+
+    #[test]
+    fn metadata_is_cached_per_pool() {
+        let pool = lazy_pool("users");
+        let metadata = pool.metadata();
+        assert_eq!(metadata.system, "mysql");
+        assert_eq!(metadata.name.as_deref(), Some("users"));
+        assert_eq!(metadata.host.as_deref(), Some("db.example.com"));
+        assert_eq!(metadata.port, Some(3306));
+
+        assert!(Arc::ptr_eq(&metadata, &pool.metadata()));
+        assert!(Arc::ptr_eq(&metadata, &pool.clone().metadata()));
+        assert!(!Arc::ptr_eq(&metadata, &lazy_pool("users").metadata()));
+    }
+
+    #[test]
+    fn metadata_is_recomputed_for_new_options() {
+        let pool = lazy_pool("users");
+        let metadata = pool.metadata();
+
+        pool.set_connect_options(MySqlConnectOptions::new().host("db.example.com").database("orders"));
+        let updated = pool.metadata();
+        assert!(!Arc::ptr_eq(&metadata, &updated));
+        assert_eq!(updated.name.as_deref(), Some("orders"));
+        assert!(Arc::ptr_eq(&updated, &pool.clone().metadata()));
+    }
+}
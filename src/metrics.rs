@@ -0,0 +1,142 @@
+use std::{
+    fmt::Write,
+    io,
+    net::{Ipv4Addr, Ipv6Addr, SocketAddr, ToSocketAddrs, UdpSocket},
+    sync::mpsc::{self, RecvTimeoutError, Sender},
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+use sqlx::{Database, Pool};
+
+use crate::{
+    InstrumentedPool, PoolMetadata,
+    metadata::{ConnectionMetadata, sealed::Metadata as _},
+};
+
+/// Periodically publishes the gauges of an [`InstrumentedPool`] to the DogStatsD server of a
+/// Datadog agent.
+///
+/// Every interval, it sends
+///
+/// - `sqlx.pool.size`, the number of open connections,
+/// - `sqlx.pool.idle`, the number of idle connections,
+/// - `sqlx.pool.max_connections`, the maximum number of connections, and
+/// - `sqlx.pool.acquire_timeouts`, the number of connection acquisitions that timed out since the
+///   last report, as a count,
+///
+/// tagged with the same `db.system`, `db.name` and `out.host` as query spans. Only acquisitions
+/// through the [`InstrumentedPool`] are counted, not those on the wrapped pool.
+///
+/// ```
+/// # use std::{net::UdpSocket, time::Duration};
+/// # use sqlx_datadog::{InstrumentedPool, PoolMetricsExporter};
+/// #
+/// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// let agent = UdpSocket::bind("127.0.0.1:0")?;
+/// let pool = sqlx::mysql::MySqlPoolOptions::new()
+///     .max_connections(5)
+///     .max_lifetime(None)
+///     .idle_timeout(None)
+///     .connect_lazy("mysql://db.example.com/users")?;
+/// let pool = InstrumentedPool::new(pool);
+/// let _exporter = PoolMetricsExporter::spawn(&pool, agent.local_addr()?, Duration::from_millis(10))?;
+///
+/// let mut report = [0; 1024];
+/// let len = agent.recv(&mut report)?;
+/// assert_eq!(
+///     std::str::from_utf8(&report[..len])?,
+///     "sqlx.pool.size:0|g|#db.system:mysql,db.name:users,out.host:db.example.com\n\
+///      sqlx.pool.idle:0|g|#db.system:mysql,db.name:users,out.host:db.example.com\n\
+///      sqlx.pool.max_connections:5|g|#db.system:mysql,db.name:users,out.host:db.example.com\n\
+///      sqlx.pool.acquire_timeouts:0|c|#db.system:mysql,db.name:users,out.host:db.example.com",
+/// );
+/// # Ok(())
+/// # }
+/// ```
+///
+/// It stops when dropped, or once the pool is closed.
+#[derive(Debug)]
+pub struct PoolMetricsExporter {
+    /// Stops the thread when dropped.
+    stop: Option<Sender<()>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl PoolMetricsExporter {
+    /// Starts publishing the gauges of `pool` to the DogStatsD server at `agent` every `interval`,
+    /// usually `127.0.0.1:8125`.
+    ///
+    /// Reports are sent from a background thread, and dropped if the agent can't be reached.
+    pub fn spawn<DB>(
+        pool: &InstrumentedPool<DB>,
+        agent: impl ToSocketAddrs,
+        interval: Duration,
+    ) -> io::Result<Self>
+    where
+        DB: Database,
+        Pool<DB>: PoolMetadata,
+    {
+        let agent = agent
+            .to_socket_addrs()?
+            .next()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "no DogStatsD address"))?;
+        let local: SocketAddr = match agent {
+            SocketAddr::V4(_) => (Ipv4Addr::UNSPECIFIED, 0).into(),
+            SocketAddr::V6(_) => (Ipv6Addr::UNSPECIFIED, 0).into(),
+        };
+        let socket = UdpSocket::bind(local)?;
+        socket.connect(agent)?;
+
+        let tags = tags(&pool.metadata());
+        let pool = pool.clone();
+        let (stop, stopped) = mpsc::channel();
+        let thread = thread::Builder::new().name("sqlx-datadog-metrics".into()).spawn(move || {
+            let mut reported_timeouts = 0;
+            while let Err(RecvTimeoutError::Timeout) = stopped.recv_timeout(interval) {
+                if pool.is_closed() {
+                    break;
+                }
+                let acquire_timeouts = pool.acquire_timeouts();
+                let report = [
+                    ("sqlx.pool.size", u64::from(pool.size()), "g"),
+                    ("sqlx.pool.idle", pool.num_idle() as u64, "g"),
+                    ("sqlx.pool.max_connections", u64::from(pool.options().get_max_connections()), "g"),
+                    ("sqlx.pool.acquire_timeouts", acquire_timeouts - reported_timeouts, "c"),
+                ]
+                .map(|(name, value, kind)| format!("{name}:{value}|{kind}|#{tags}"))
+                .join("\n");
+                reported_timeouts = acquire_timeouts;
+                // DogStatsD is fire and forget, so an unreachable agent just misses a report.
+                let _ = socket.send(report.as_bytes());
+            }
+        })?;
+        Ok(Self { stop: Some(stop), thread: Some(thread) })
+    }
+}
+
+impl Drop for PoolMetricsExporter {
+    fn drop(&mut self) {
+        drop(self.stop.take());
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// Formats the connection tags of a pool as DogStatsD tags.
+fn tags(metadata: &ConnectionMetadata) -> String {
+    let mut tags = format!("db.system:{}", metadata.system);
+    if let Some(name) = &metadata.name {
+        let _ = write!(tags, ",db.name:{}", tag_value(name));
+    }
+    if let Some(host) = &metadata.host {
+        let _ = write!(tags, ",out.host:{}", tag_value(host));
+    }
+    tags
+}
+
+/// Replaces the characters DogStatsD uses as delimiters in a tag value.
+fn tag_value(value: &str) -> String {
+    value.replace([',', '|', '#', '\n'], "_")
+}
@@ -0,0 +1,128 @@
+use std::{fmt::Debug, sync::RwLock};
+
+use tracing::Span;
+
+/// The key for digests of query parameters, see [`set_param_digest_key`].
+static DIGEST_KEY: RwLock<Option<Vec<u8>>> = RwLock::new(None);
+
+/// Sets the secret key for the digests of query parameters that `instrument_query` captures using
+/// `capture_params(hash(...))`.
+///
+/// Digests are keyed, so values like email addresses can't be recovered by hashing likely
+/// candidates without the key. Until a key is set, a random one is used, so digests only match
+/// within a process. To correlate values across processes, set the same key in each of them:
+///
+/// ```
+/// # fn main() -> Result<(), std::env::VarError> {
+/// # unsafe { std::env::set_var("PARAM_DIGEST_KEY", "secret") };
+/// sqlx_datadog::set_param_digest_key(std::env::var("PARAM_DIGEST_KEY")?);
+/// # Ok(())
+/// # }
+/// ```
+pub fn set_param_digest_key(key: impl Into<Vec<u8>>) {
+    *DIGEST_KEY.write().expect("digest key poisoned") = Some(key.into());
+}
+
+/// How `instrument_query` records a query parameter it captured.
+#[derive(Clone, Copy, Debug)]
+pub enum Capture {
+    /// Records the value, truncated to `max_len` characters.
+    Value { max_len: usize },
+    /// Records a keyed digest of the value, to tell values apart without recording them.
+    Digest,
+    /// Records `?` in place of the value.
+    Redacted,
+}
+
+/// Records the parameters of a query as `db.sql.params.1`, `db.sql.params.2` and so on.
+///
+/// Span fields can't be numbered, so this sets OpenTelemetry attributes instead.
+#[cfg(feature = "opentelemetry")]
+pub fn capture_params(span: &Span, params: &[(&dyn Debug, Capture)]) {
+    use tracing_opentelemetry::OpenTelemetrySpanExt;
+
+    for (index, (value, capture)) in params.iter().enumerate() {
+        span.set_attribute(format!("db.sql.params.{}", index + 1), param_value(*value, *capture));
+    }
+}
+
+/// Records the parameters of a query, which requires the `opentelemetry` feature.
+///
+/// Without it, `capture_params` is a deprecation warning:
+///
+/// ```compile_fail
+/// #![deny(deprecated)]
+/// # #[macro_use] extern crate sqlx_datadog;
+/// #
+/// #[instrument_query(skip(db), capture_params(allow(limit)))]
+/// async fn user_ids(db: &sqlx::MySqlPool, limit: i64) -> Result<Vec<i64>, sqlx::Error> {
+///     let query = "SELECT id FROM users LIMIT ?";
+///     sqlx::query_scalar(query).bind(limit).fetch_all(db).await
+/// }
+/// ```
+///
+/// Which can be silenced:
+///
+/// ```
+/// #![deny(deprecated)]
+/// # #[macro_use] extern crate sqlx_datadog;
+/// #
+/// #[allow(deprecated)]
+/// #[instrument_query(skip(db), capture_params(allow(limit)))]
+/// async fn user_ids(db: &sqlx::MySqlPool, limit: i64) -> Result<Vec<i64>, sqlx::Error> {
+///     let query = "SELECT id FROM users LIMIT ?";
+///     sqlx::query_scalar(query).bind(limit).fetch_all(db).await
+/// }
+/// ```
+#[cfg(not(feature = "opentelemetry"))]
+#[deprecated(
+    note = "`capture_params` requires the `opentelemetry` feature of `sqlx-datadog`, without it, no \
+            parameters are recorded"
+)]
+pub fn capture_params(_span: &Span, _params: &[(&dyn Debug, Capture)]) {}
+
+/// Formats a query parameter according to its redaction policy.
+#[cfg(feature = "opentelemetry")]
+fn param_value(value: &dyn Debug, capture: Capture) -> String {
+    use std::fmt::Write;
+
+    use hmac::{Hmac, Mac};
+    use sha2::Sha256;
+
+    match capture {
+        Capture::Value { max_len } => {
+            let value = format!("{value:?}");
+            match value.char_indices().nth(max_len) {
+                Some((end, _)) => format!("{}...", &value[..end]),
+                None => value,
+            }
+        }
+        Capture::Digest => {
+            let mut mac = match &*DIGEST_KEY.read().expect("digest key poisoned") {
+                Some(key) => Hmac::<Sha256>::new_from_slice(key),
+                None => Hmac::<Sha256>::new_from_slice(random_key()),
+            }
+            .expect("HMAC takes keys of any length");
+            mac.update(format!("{value:?}").as_bytes());
+
+            let mut digest = String::from("hmac-sha256:");
+            for byte in mac.finalize().into_bytes() {
+                let _ = write!(digest, "{byte:02x}");
+            }
+            digest
+        }
+        Capture::Redacted => "?".to_string(),
+    }
+}
+
+/// Returns the digest key used until one is set, which is random per process.
+#[cfg(feature = "opentelemetry")]
+fn random_key() -> &'static [u8] {
+    static KEY: std::sync::OnceLock<[u8; 32]> = std::sync::OnceLock::new();
+
+    KEY.get_or_init(|| {
+        let mut key = [0; 32];
+        getrandom::getrandom(&mut key).expect("failed to generate a digest key");
+        key
+    })
+}
@@ -0,0 +1,252 @@
+use std::{
+    future,
+    ops::Deref,
+    sync::{
+        Arc,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::Instant,
+};
+
+use futures_channel::mpsc;
+use futures_core::{future::BoxFuture, stream::BoxStream};
+use futures_util::{FutureExt, SinkExt, StreamExt, stream};
+use sqlx::{
+    Acquire, Database, Describe, Either, Error, Execute, Executor, Pool, Transaction,
+    pool::PoolConnection,
+};
+use tracing::{Instrument, Span, field::Empty};
+
+use crate::{
+    ConnectionTags, InstrumentedTransaction, RowCount,
+    executor::{InstrumentedStream, instrument_future, statement_span},
+    record_error,
+    service::record_default_peer_service,
+};
+
+/// A pool which records a span for every query executed on it, see
+/// [`InstrumentedExecutor`](crate::InstrumentedExecutor).
+///
+/// ```
+/// # use sqlx_datadog::InstrumentedPool;
+/// #
+/// async fn delete_user(pool: &InstrumentedPool<sqlx::MySql>, user_id: i64) -> Result<(), sqlx::Error> {
+///     sqlx::query("DELETE FROM users WHERE id = ?").bind(user_id).execute(pool).await?;
+///     Ok(())
+/// }
+/// ```
+///
+/// Acquiring a connection for a query or transaction gets its own `sqlx.pool.acquire` child span,
+/// tagged with the pool's `db.pool.size` and `db.pool.idle` connections at the time, and the time
+/// it took is recorded on the query span, or the `BEGIN` span of the transaction, as
+/// `db.pool.wait_ms`. SQLx acquires the connection of a transaction and begins it in one step, so
+/// for transactions this includes the `BEGIN` round trip.
+///
+/// It dereferences to the wrapped pool, so everything else works like it does on a [`Pool`], except
+/// for `acquire`, which records the `sqlx.pool.acquire` span, and `begin`, which begins an
+/// [`InstrumentedTransaction`]. The same goes for its [`Acquire`] implementation.
+#[derive(Debug)]
+pub struct InstrumentedPool<DB: Database> {
+    pool: Pool<DB>,
+    /// How many acquisitions timed out, shared between clones.
+    acquire_timeouts: Arc<AtomicU64>,
+}
+
+impl<DB: Database> InstrumentedPool<DB> {
+    /// Wraps `pool`.
+    pub fn new(pool: Pool<DB>) -> Self {
+        Self { pool, acquire_timeouts: Arc::default() }
+    }
+
+    /// Returns the wrapped pool.
+    pub fn into_inner(self) -> Pool<DB> {
+        self.pool
+    }
+
+    /// Acquires a connection in a `sqlx.pool.acquire` span.
+    pub async fn acquire(&self) -> Result<PoolConnection<DB>, Error>
+    where
+        Pool<DB>: ConnectionTags,
+    {
+        self.instrument_acquisition(self.pool.acquire()).await
+    }
+
+    /// Runs `acquisition` in a `sqlx.pool.acquire` span, and records how long it took on it and
+    /// the current span.
+    async fn instrument_acquisition<T>(
+        &self,
+        acquisition: impl Future<Output = Result<T, Error>>,
+    ) -> Result<T, Error>
+    where
+        Pool<DB>: ConnectionTags,
+    {
+        let span = acquire_span();
+        self.pool.record_connection_tags(&span);
+        record_default_peer_service(&span);
+        span.record("db.pool.size", self.pool.size());
+        span.record("db.pool.idle", self.pool.num_idle());
+
+        let started_at = Instant::now();
+        let result = acquisition.instrument(span.clone()).await;
+        let wait_ms = started_at.elapsed().as_secs_f64() * 1000.0;
+        span.record("db.pool.wait_ms", wait_ms);
+        Span::current().record("db.pool.wait_ms", wait_ms);
+        if let Err(error) = &result {
+            if let Error::PoolTimedOut = error {
+                self.acquire_timeouts.fetch_add(1, Ordering::Relaxed);
+            }
+            record_error(&span, error);
+        }
+        result
+    }
+
+    /// Returns how many acquisitions on this pool and its clones have timed out so far.
+    pub(crate) fn acquire_timeouts(&self) -> u64 {
+        self.acquire_timeouts.load(Ordering::Relaxed)
+    }
+
+    /// Begins an instrumented transaction, see [`InstrumentedTransaction`].
+    pub async fn begin(&self) -> Result<InstrumentedTransaction<'static, DB>, Error>
+    where
+        Pool<DB>: ConnectionTags,
+        DB::Connection: ConnectionTags,
+    {
+        InstrumentedTransaction::begin(self).await
+    }
+}
+
+impl<'c, DB: Database> Acquire<'c> for &InstrumentedPool<DB>
+where
+    Pool<DB>: ConnectionTags,
+{
+    type Database = DB;
+
+    type Connection = PoolConnection<DB>;
+
+    fn acquire(self) -> BoxFuture<'c, Result<Self::Connection, Error>> {
+        let pool = self.clone();
+        Box::pin(async move { pool.acquire().await })
+    }
+
+    fn begin(self) -> BoxFuture<'c, Result<Transaction<'c, DB>, Error>> {
+        let pool = self.clone();
+        // SQLx only begins transactions on connections it acquires itself, so the acquisition
+        // includes sending `BEGIN`.
+        Box::pin(async move { pool.instrument_acquisition(pool.pool.begin()).await })
+    }
+}
+
+impl<DB: Database> Clone for InstrumentedPool<DB> {
+    fn clone(&self) -> Self {
+        Self { pool: self.pool.clone(), acquire_timeouts: self.acquire_timeouts.clone() }
+    }
+}
+
+impl<DB: Database> From<Pool<DB>> for InstrumentedPool<DB> {
+    fn from(pool: Pool<DB>) -> Self {
+        Self::new(pool)
+    }
+}
+
+impl<DB: Database> Deref for InstrumentedPool<DB> {
+    type Target = Pool<DB>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.pool
+    }
+}
+
+impl<DB: Database> ConnectionTags for InstrumentedPool<DB>
+where
+    Pool<DB>: ConnectionTags,
+{
+    fn record_connection_tags(&self, span: &Span) {
+        self.pool.record_connection_tags(span);
+    }
+}
+
+impl<DB: Database> Executor<'_> for &InstrumentedPool<DB>
+where
+    for<'c> &'c mut DB::Connection: Executor<'c, Database = DB>,
+    Pool<DB>: ConnectionTags,
+    DB::QueryResult: RowCount,
+{
+    type Database = DB;
+
+    fn fetch_many<'e, 'q: 'e, Q>(
+        self,
+        query: Q,
+    ) -> BoxStream<'e, Result<Either<DB::QueryResult, DB::Row>, Error>>
+    where
+        Q: 'q + Execute<'q, DB>,
+    {
+        let span = statement_span(&self.pool, query.sql());
+        let pool = InstrumentedPool::clone(self);
+        // The query stream borrows the connection, so a future owning both forwards the results
+        // through a channel.
+        let (mut sender, receiver) = mpsc::channel(0);
+        let forward = async move {
+            match pool.acquire().await {
+                Ok(mut connection) => {
+                    let _ = connection.fetch_many(query).map(Ok).forward(&mut sender).await;
+                }
+                Err(error) => {
+                    let _ = sender.send(Err(error)).await;
+                }
+            }
+        };
+        let forward = forward.into_stream().filter_map(|()| future::ready(None));
+        Box::pin(InstrumentedStream::new(Box::pin(stream::select(receiver, forward)), span))
+    }
+
+    fn fetch_optional<'e, 'q: 'e, Q>(self, query: Q) -> BoxFuture<'e, Result<Option<DB::Row>, Error>>
+    where
+        Q: 'q + Execute<'q, DB>,
+    {
+        let span = statement_span(&self.pool, query.sql());
+        let pool = InstrumentedPool::clone(self);
+        let future = async move {
+            let mut connection = pool.acquire().await?;
+            connection.fetch_optional(query).await
+        };
+        Box::pin(instrument_future(future, span))
+    }
+
+    fn prepare_with<'e, 'q: 'e>(
+        self,
+        sql: &'q str,
+        parameters: &'e [DB::TypeInfo],
+    ) -> BoxFuture<'e, Result<DB::Statement<'q>, Error>> {
+        self.pool.prepare_with(sql, parameters)
+    }
+
+    fn describe<'e, 'q: 'e>(self, sql: &'q str) -> BoxFuture<'e, Result<Describe<DB>, Error>> {
+        self.pool.describe(sql)
+    }
+}
+
+/// Creates a connection acquisition span.
+fn acquire_span() -> Span {
+    tracing::info_span!(
+        "sqlx.pool.acquire",
+        span.kind = "client",
+        span.type = "sql",
+        component = "sqlx",
+        operation = "sqlx.pool.acquire",
+        resource = "ACQUIRE",
+        peer.service = Empty,
+        peer.db.name = Empty,
+        peer.hostname = Empty,
+        out.host = Empty,
+        out.port = Empty,
+        db.system = Empty,
+        db.instance = Empty,
+        db.name = Empty,
+        db.pool.size = Empty,
+        db.pool.idle = Empty,
+        db.pool.wait_ms = Empty,
+        error.type = Empty,
+        error.message = Empty,
+        error.stack = Empty,
+    )
+}
@@ -0,0 +1,269 @@
+use std::{
+    borrow::Cow,
+    env,
+    fmt::Write,
+    str::FromStr,
+    sync::{
+        OnceLock,
+        atomic::{AtomicU8, Ordering},
+    },
+};
+
+use tracing::Span;
+
+/// How much trace context to propagate to Datadog Database Monitoring (DBM).
+///
+/// Defaults to the value of `DD_DBM_PROPAGATION_MODE`, like in other Datadog tracing libraries,
+/// and can be overridden using [`set_propagation_mode`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum PropagationMode {
+    /// Don't propagate anything.
+    #[default]
+    Disabled,
+    /// Propagate the service, environment and version, to link query samples to services.
+    Service,
+    /// Additionally propagate the trace context of the current span, to link query samples to
+    /// traces.
+    ///
+    /// This requires the `opentelemetry` feature, without it, this behaves like `Service`.
+    Full,
+}
+
+impl FromStr for PropagationMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "disabled" => Ok(Self::Disabled),
+            "service" => Ok(Self::Service),
+            "full" => Ok(Self::Full),
+            _ => Err(format!("invalid DBM propagation mode: {s}")),
+        }
+    }
+}
+
+/// Sentinel for an unset propagation mode override.
+const MODE_UNSET: u8 = u8::MAX;
+
+/// The propagation mode override.
+static MODE: AtomicU8 = AtomicU8::new(MODE_UNSET);
+
+/// Sets the DBM propagation mode, overriding `DD_DBM_PROPAGATION_MODE`.
+pub fn set_propagation_mode(mode: PropagationMode) {
+    MODE.store(mode as u8, Ordering::Relaxed);
+}
+
+/// Returns the current DBM propagation mode.
+pub fn propagation_mode() -> PropagationMode {
+    static ENV_MODE: OnceLock<PropagationMode> = OnceLock::new();
+
+    match MODE.load(Ordering::Relaxed) {
+        0 => PropagationMode::Disabled,
+        1 => PropagationMode::Service,
+        2 => PropagationMode::Full,
+        _ => *ENV_MODE.get_or_init(|| {
+            env::var("DD_DBM_PROPAGATION_MODE")
+                .ok()
+                .and_then(|mode| mode.parse().ok())
+                .unwrap_or_default()
+        }),
+    }
+}
+
+/// Returns the DBM propagation comment for the current span, if propagation is enabled.
+///
+/// `db_service` is the name under which the database is known to Datadog. Environment, service
+/// and version are taken from `DD_ENV`, `DD_SERVICE` and `DD_VERSION`. The comment follows the
+/// SQLCommenter format, e.g. `/*dddbs='users-db',dde='prod',ddps='api',ddpv='1.2.3'*/`.
+///
+/// In `Full` mode, a valid trace context gets added as `traceparent`, and the current span is
+/// tagged with `_dd.dbm_trace_injected`.
+pub fn propagation_comment(db_service: &str) -> Option<String> {
+    /// Environment tags, which never change.
+    static SERVICE_TAGS: OnceLock<Vec<(&str, String)>> = OnceLock::new();
+
+    let mode = propagation_mode();
+    if mode == PropagationMode::Disabled {
+        return None;
+    }
+
+    let service_tags = SERVICE_TAGS.get_or_init(|| {
+        [("dde", "DD_ENV"), ("ddps", "DD_SERVICE"), ("ddpv", "DD_VERSION")]
+            .into_iter()
+            .filter_map(|(key, var)| Some((key, env::var(var).ok()?)))
+            .collect()
+    });
+    let mut tags = vec![("dddbs", db_service.to_string())];
+    tags.extend(service_tags.iter().cloned());
+
+    if mode == PropagationMode::Full && let Some(traceparent) = traceparent(&Span::current()) {
+        tags.push(("traceparent", traceparent));
+        Span::current().record("_dd.dbm_trace_injected", "true");
+    }
+
+    let mut comment = String::from("/*");
+    for (i, (key, value)) in tags.iter().enumerate() {
+        if i > 0 {
+            comment.push(',');
+        }
+        let _ = write!(comment, "{key}='{}'", encode(value));
+    }
+    comment.push_str("*/");
+    Some(comment)
+}
+
+/// Prepends the DBM propagation comment for the current span to `query`, if propagation is
+/// enabled.
+///
+/// Note that in `Full` mode, every execution has unique query text, which defeats SQLx's prepared
+/// statement cache. Consider executing such queries with `persistent(false)`.
+///
+/// ```
+/// # use sqlx_datadog::{instrument_query, propagate};
+/// #
+/// const DELETE_USER: &str = "DELETE FROM users WHERE id = ?";
+///
+/// #[instrument_query(skip(db), query = DELETE_USER)]
+/// async fn delete_user(db: &sqlx::MySqlPool, user_id: i64) -> Result<(), sqlx::Error> {
+///     let query = propagate(DELETE_USER, "users-db");
+///     sqlx::query(&query).bind(user_id).persistent(false).execute(db).await?;
+///     Ok(())
+/// }
+/// ```
+pub fn propagate<'q>(query: &'q str, db_service: &str) -> Cow<'q, str> {
+    match propagation_comment(db_service) {
+        Some(comment) => Cow::Owned(format!("{comment} {query}")),
+        None => Cow::Borrowed(query),
+    }
+}
+
+/// Returns the W3C `traceparent` of a span, if it has a valid trace context.
+#[cfg(feature = "opentelemetry")]
+fn traceparent(span: &Span) -> Option<String> {
+    use opentelemetry::trace::TraceContextExt;
+    use tracing_opentelemetry::OpenTelemetrySpanExt;
+
+    let context = span.context();
+    let span_context = context.span().span_context().clone();
+    span_context.is_valid().then(|| {
+        format!(
+            "00-{}-{}-{:02x}",
+            span_context.trace_id(),
+            span_context.span_id(),
+            span_context.trace_flags().to_u8(),
+        )
+    })
+}
+
+/// Returns the W3C `traceparent` of a span, which requires the `opentelemetry` feature.
+#[cfg(not(feature = "opentelemetry"))]
+fn traceparent(_span: &Span) -> Option<String> {
+    None
+}
+
+/// URL-encodes a comment value, as required by SQLCommenter.
+fn encode(value: &str) -> String {
+    let mut encoded = String::with_capacity(value.len());
+    for byte in value.bytes() {
+        if byte.is_ascii_alphanumeric() || b"-._~".contains(&byte) {
+            encoded.push(byte as char);
+        } else {
+            let _ = write!(encoded, "%{byte:02X}");
+        }
+    }
+    encoded
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Mutex, MutexGuard, Once};
+
+    use super::*;
+
+    /// Serializes tests using the global propagation mode, and sets the environment tags.
+    fn lock() -> MutexGuard<'static, ()> {
+        static LOCK: Mutex<()> = Mutex::new(());
+        static ENV: Once = Once::new();
+
+        ENV.call_once(|| {
+            // SAFETY: Nothing else in these tests reads the environment concurrently.
+            unsafe {
+                env::set_var("DD_ENV", "prod");
+                env::set_var("DD_SERVICE", "api");
+                env::set_var("DD_VERSION", "1.2.3");
+            }
+        });
+        LOCK.lock().unwrap_or_else(|error| error.into_inner())
+    }
+
+    #[test]
+    fn parse_propagation_mode() {
+        assert_eq!("disabled".parse(), Ok(PropagationMode::Disabled));
+        assert_eq!("service".parse(), Ok(PropagationMode::Service));
+        assert_eq!("full".parse(), Ok(PropagationMode::Full));
+        assert!("Full".parse::<PropagationMode>().is_err());
+        assert!("".parse::<PropagationMode>().is_err());
+    }
+
+    #[test]
+    fn propagate_disabled() {
+        let _lock = lock();
+        set_propagation_mode(PropagationMode::Disabled);
+
+        assert_eq!(propagation_comment("users-db"), None);
+        assert!(matches!(propagate("SELECT 1", "users-db"), Cow::Borrowed("SELECT 1")));
+    }
+
+    #[test]
+    fn propagate_service() {
+        let _lock = lock();
+        set_propagation_mode(PropagationMode::Service);
+
+        assert_eq!(
+            propagation_comment("users db's 100%~").as_deref(),
+            Some("/*dddbs='users%20db%27s%20100%25~',dde='prod',ddps='api',ddpv='1.2.3'*/"),
+        );
+        assert_eq!(
+            propagate("SELECT 1", "users-db"),
+            "/*dddbs='users-db',dde='prod',ddps='api',ddpv='1.2.3'*/ SELECT 1",
+        );
+    }
+
+    #[cfg(feature = "opentelemetry")]
+    #[test]
+    fn propagate_full() {
+        use opentelemetry::{
+            Value,
+            trace::{TraceContextExt as _, TracerProvider as _},
+        };
+        use opentelemetry_sdk::trace::{InMemorySpanExporter, SdkTracerProvider};
+        use tracing_opentelemetry::OpenTelemetrySpanExt as _;
+        use tracing_subscriber::layer::SubscriberExt as _;
+
+        let _lock = lock();
+        set_propagation_mode(PropagationMode::Full);
+
+        let exporter = InMemorySpanExporter::default();
+        let provider = SdkTracerProvider::builder().with_simple_exporter(exporter.clone()).build();
+        let subscriber = tracing_subscriber::registry()
+            .with(tracing_opentelemetry::layer().with_tracer(provider.tracer("sqlx-datadog")));
+        let (comment, context) = tracing::subscriber::with_default(subscriber, || {
+            let span = tracing::info_span!("query", _dd.dbm_trace_injected = tracing::field::Empty);
+            let _entered = span.enter();
+            (propagation_comment("users-db").unwrap(), span.context().span().span_context().clone())
+        });
+
+        let traceparent = format!("00-{}-{}-01", context.trace_id(), context.span_id());
+        assert_eq!(
+            comment,
+            format!("/*dddbs='users-db',dde='prod',ddps='api',ddpv='1.2.3',traceparent='{traceparent}'*/"),
+        );
+        let [span] = exporter.get_finished_spans().unwrap().try_into().unwrap();
+        assert_eq!(span.span_context.trace_id(), context.trace_id());
+        assert!(
+            span.attributes
+                .iter()
+                .any(|kv| kv.key.as_str() == "_dd.dbm_trace_injected" && kv.value == Value::from("true"))
+        );
+    }
+}
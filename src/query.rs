@@ -0,0 +1,36 @@
+use tracing::Span;
+
+/// Records `resource`, `db.statement`, `db.operation` and `db.sql.table` tags for query text on
+/// `span`.
+///
+/// The resource is obfuscated the same way `instrument_query` does it for literal query text.
+///
+/// `instrument_query` does this automatically for query text it can find at compile time. Use this
+/// for SQL that only exists at runtime, e.g. when built using [`sqlx::QueryBuilder`]:
+///
+/// ```
+/// # use sqlx_datadog::{instrument_query, record_query};
+/// #
+/// #[instrument_query(skip(db, ids))]
+/// async fn delete_users(db: &sqlx::MySqlPool, ids: &[i64]) -> Result<(), sqlx::Error> {
+///     let mut builder = sqlx::QueryBuilder::new("DELETE FROM users WHERE id IN (");
+///     let mut separated = builder.separated(", ");
+///     for id in ids {
+///         separated.push_bind(id);
+///     }
+///     separated.push_unseparated(")");
+///
+///     record_query(&tracing::Span::current(), builder.sql());
+///     builder.build().execute(db).await?;
+///     Ok(())
+/// }
+/// ```
+pub fn record_query(span: &Span, query: &str) {
+    span.record("resource", sqlx_datadog_sql::obfuscate(query));
+    span.record("db.statement", query.trim());
+    span.record("db.operation", sqlx_datadog_sql::operation(query));
+    let tables = sqlx_datadog_sql::tables(query);
+    if !tables.is_empty() {
+        span.record("db.sql.table", tables.join(","));
+    }
+}
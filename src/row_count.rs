@@ -0,0 +1,40 @@
+/// Counts the rows returned or affected by a query, for the `db.row_count` tag.
+///
+/// This is what `instrument_query(row_count)` uses to inspect successful return values.
+pub trait RowCount {
+    /// Returns the number of rows returned or affected.
+    fn row_count(&self) -> u64;
+}
+
+impl<T> RowCount for Vec<T> {
+    fn row_count(&self) -> u64 {
+        self.len() as u64
+    }
+}
+
+impl<T> RowCount for Option<T> {
+    fn row_count(&self) -> u64 {
+        self.is_some().into()
+    }
+}
+
+#[cfg(feature = "mysql")]
+impl RowCount for sqlx::mysql::MySqlQueryResult {
+    fn row_count(&self) -> u64 {
+        self.rows_affected()
+    }
+}
+
+#[cfg(feature = "postgres")]
+impl RowCount for sqlx::postgres::PgQueryResult {
+    fn row_count(&self) -> u64 {
+        self.rows_affected()
+    }
+}
+
+#[cfg(feature = "sqlite")]
+impl RowCount for sqlx::sqlite::SqliteQueryResult {
+    fn row_count(&self) -> u64 {
+        self.rows_affected()
+    }
+}
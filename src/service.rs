@@ -0,0 +1,26 @@
+use std::sync::RwLock;
+
+use tracing::Span;
+
+/// The `peer.service` of queries that don't set their own.
+static DEFAULT_PEER_SERVICE: RwLock<Option<String>> = RwLock::new(None);
+
+/// Sets the `peer.service` tag of instrumented queries that don't set their own using the
+/// `peer_service` argument.
+///
+/// This makes the database show up as a separate service in the Datadog service map, which uses
+/// `peer.service` to infer services. Without it, Datadog falls back to `peer.db.name`.
+///
+/// ```
+/// sqlx_datadog::set_default_peer_service("users-db");
+/// ```
+pub fn set_default_peer_service(service: impl Into<String>) {
+    *DEFAULT_PEER_SERVICE.write().expect("default peer service poisoned") = Some(service.into());
+}
+
+/// Records the default peer service on `span`, if there is one.
+pub fn record_default_peer_service(span: &Span) {
+    if let Some(service) = &*DEFAULT_PEER_SERVICE.read().expect("default peer service poisoned") {
+        span.record("peer.service", service.as_str());
+    }
+}
@@ -0,0 +1,199 @@
+use std::ops::{Deref, DerefMut};
+
+use futures_core::{future::BoxFuture, stream::BoxStream};
+use sqlx::{Acquire, Database, Describe, Either, Error, Execute, Executor, Transaction};
+use tracing::{Instrument, Span, field::Empty};
+
+use crate::{
+    ConnectionTags, InstrumentedExecutor, RowCount, executor::statement_span, record_error,
+    service::record_default_peer_service,
+};
+
+/// A transaction with a span covering it from begin to commit or rollback.
+///
+/// The span is tagged with `db.transaction = true` and the connection tags, and has child spans
+/// for `BEGIN`, `COMMIT` or `ROLLBACK`, as well as every query executed on the transaction, which
+/// get the same tags as `instrument_query`. How the transaction ended is recorded as
+/// `db.transaction.outcome`, which is one of `commit`, `rollback` or `implicit_rollback` if it was
+/// dropped without either.
+///
+/// ```
+/// # use sqlx_datadog::InstrumentedPool;
+/// #
+/// async fn transfer(pool: &InstrumentedPool<sqlx::MySql>, from: i64, to: i64) -> Result<(), sqlx::Error> {
+///     let mut tx = pool.begin().await?;
+///     sqlx::query("UPDATE accounts SET balance = balance - 1 WHERE id = ?").bind(from).execute(&mut tx).await?;
+///     sqlx::query("UPDATE accounts SET balance = balance + 1 WHERE id = ?").bind(to).execute(&mut tx).await?;
+///     tx.commit().await
+/// }
+/// ```
+///
+/// Like [`Transaction`], it dereferences to the connection, which executes queries without
+/// creating a span, e.g. to pass it to functions using `instrument_query`.
+#[derive(Debug)]
+pub struct InstrumentedTransaction<'c, DB: Database> {
+    /// The transaction, until it is committed or rolled back.
+    transaction: Option<Transaction<'c, DB>>,
+    span: Span,
+}
+
+impl<'c, DB: Database> InstrumentedTransaction<'c, DB>
+where
+    DB::Connection: ConnectionTags,
+{
+    /// Begins a transaction on a pool or connection.
+    pub async fn begin<A>(connection: A) -> Result<Self, Error>
+    where
+        A: Acquire<'c, Database = DB> + ConnectionTags,
+    {
+        let span = transaction_span();
+        connection.record_connection_tags(&span);
+        record_default_peer_service(&span);
+
+        let begin_span = span.in_scope(|| statement_span(&connection, "BEGIN"));
+        match connection.begin().instrument(begin_span.clone()).await {
+            Ok(transaction) => Ok(Self { transaction: Some(transaction), span }),
+            Err(error) => {
+                record_error(&begin_span, &error);
+                record_error(&span, &error);
+                Err(error)
+            }
+        }
+    }
+
+    /// Commits the transaction.
+    pub async fn commit(mut self) -> Result<(), Error> {
+        self.finish(true).await
+    }
+
+    /// Rolls back the transaction.
+    pub async fn rollback(mut self) -> Result<(), Error> {
+        self.finish(false).await
+    }
+
+    /// Returns the span of the transaction, e.g. to make it the parent of other spans.
+    pub fn span(&self) -> &Span {
+        &self.span
+    }
+
+    /// Commits or rolls back the transaction in a child span.
+    async fn finish(&mut self, commit: bool) -> Result<(), Error> {
+        let (statement, outcome) = if commit { ("COMMIT", "commit") } else { ("ROLLBACK", "rollback") };
+        let transaction = self.transaction.take().expect("transaction already ended");
+        let span = self.span.in_scope(|| statement_span(&transaction, statement));
+        let result = if commit {
+            transaction.commit().instrument(span.clone()).await
+        } else {
+            transaction.rollback().instrument(span.clone()).await
+        };
+        match &result {
+            Ok(()) => {
+                self.span.record("db.transaction.outcome", outcome);
+            }
+            Err(error) => {
+                record_error(&span, error);
+                record_error(&self.span, error);
+            }
+        }
+        result
+    }
+}
+
+impl<DB: Database> Deref for InstrumentedTransaction<'_, DB> {
+    type Target = DB::Connection;
+
+    fn deref(&self) -> &Self::Target {
+        self.transaction.as_ref().expect("transaction already ended")
+    }
+}
+
+impl<DB: Database> DerefMut for InstrumentedTransaction<'_, DB> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        self.transaction.as_mut().expect("transaction already ended")
+    }
+}
+
+impl<DB: Database> Drop for InstrumentedTransaction<'_, DB> {
+    fn drop(&mut self) {
+        // The transaction rolls itself back when dropped.
+        if self.transaction.is_some() {
+            self.span.record("db.transaction.outcome", "implicit_rollback");
+        }
+    }
+}
+
+impl<'t, DB: Database> Executor<'t> for &'t mut InstrumentedTransaction<'_, DB>
+where
+    for<'a> &'a mut DB::Connection: Executor<'a, Database = DB>,
+    DB::Connection: ConnectionTags,
+    DB::QueryResult: RowCount,
+{
+    type Database = DB;
+
+    fn fetch_many<'e, 'q: 'e, Q>(
+        self,
+        query: Q,
+    ) -> BoxStream<'e, Result<Either<DB::QueryResult, DB::Row>, Error>>
+    where
+        't: 'e,
+        Q: 'q + Execute<'q, DB>,
+    {
+        let span = self.span.clone();
+        span.in_scope(|| InstrumentedExecutor::new(&mut **self).fetch_many(query))
+    }
+
+    fn fetch_optional<'e, 'q: 'e, Q>(self, query: Q) -> BoxFuture<'e, Result<Option<DB::Row>, Error>>
+    where
+        't: 'e,
+        Q: 'q + Execute<'q, DB>,
+    {
+        let span = self.span.clone();
+        span.in_scope(|| InstrumentedExecutor::new(&mut **self).fetch_optional(query))
+    }
+
+    fn prepare_with<'e, 'q: 'e>(
+        self,
+        sql: &'q str,
+        parameters: &'e [DB::TypeInfo],
+    ) -> BoxFuture<'e, Result<DB::Statement<'q>, Error>>
+    where
+        't: 'e,
+    {
+        (&mut **self).prepare_with(sql, parameters)
+    }
+
+    fn describe<'e, 'q: 'e>(self, sql: &'q str) -> BoxFuture<'e, Result<Describe<DB>, Error>>
+    where
+        't: 'e,
+    {
+        (&mut **self).describe(sql)
+    }
+}
+
+/// Creates a transaction span.
+fn transaction_span() -> Span {
+    tracing::info_span!(
+        "sqlx.transaction",
+        span.kind = "client",
+        span.type = "sql",
+        component = "sqlx",
+        operation = "sqlx.transaction",
+        resource = "TRANSACTION",
+        peer.service = Empty,
+        peer.db.name = Empty,
+        peer.hostname = Empty,
+        out.host = Empty,
+        out.port = Empty,
+        db.system = Empty,
+        db.instance = Empty,
+        db.name = Empty,
+        db.transaction = true,
+        db.transaction.outcome = Empty,
+        error.type = Empty,
+        error.message = Empty,
+        error.stack = Empty,
+        db.error.code = Empty,
+        db.error.constraint = Empty,
+        db.error.kind = Empty,
+    )
+}
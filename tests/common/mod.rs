@@ -0,0 +1,22 @@
+use sqlx::{SqlitePool, sqlite::SqlitePoolOptions};
+
+/// Opens a pool on an in-memory database with a `users` table of three rows, with only the first
+/// one active.
+pub async fn pool() -> SqlitePool {
+    // Every connection opens a new in-memory database, so there must only be one.
+    let pool = SqlitePoolOptions::new().max_connections(1).connect("sqlite::memory:").await.unwrap();
+    sqlx::query("CREATE TABLE users (id INTEGER PRIMARY KEY, email TEXT, name TEXT, active BOOLEAN)")
+        .execute(&pool)
+        .await
+        .unwrap();
+    sqlx::query(
+        "INSERT INTO users VALUES
+            (1, 'robin@example.com', 'Robin Schroer', TRUE),
+            (2, 'alex@example.com', 'Alex', FALSE),
+            (3, 'sam@example.com', 'Sam', FALSE)",
+    )
+    .execute(&pool)
+    .await
+    .unwrap();
+    pool
+}
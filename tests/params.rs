@@ -0,0 +1,117 @@
+mod common;
+
+use opentelemetry::trace::TracerProvider;
+use opentelemetry_sdk::trace::{InMemorySpanExporter, SdkTracerProvider, SpanData};
+use sqlx::SqlitePool;
+use sqlx_datadog::{instrument_query, set_param_digest_key};
+use tracing::subscriber::DefaultGuard;
+use tracing_subscriber::layer::SubscriberExt;
+
+/// Exports the OpenTelemetry spans created on the current thread while the guard is held.
+fn capture() -> (InMemorySpanExporter, DefaultGuard) {
+    let exporter = InMemorySpanExporter::default();
+    let provider = SdkTracerProvider::builder().with_simple_exporter(exporter.clone()).build();
+    let subscriber = tracing_subscriber::registry()
+        .with(tracing_opentelemetry::layer().with_tracer(provider.tracer("sqlx-datadog")));
+    (exporter, tracing::subscriber::set_default(subscriber))
+}
+
+/// Returns the only exported span named `name`.
+fn span(exporter: &InMemorySpanExporter, name: &str) -> SpanData {
+    let spans = exporter.get_finished_spans().unwrap();
+    let [span] = spans.into_iter().filter(|span| span.name == name).collect::<Vec<_>>().try_into().unwrap();
+    span
+}
+
+/// Returns the value of an attribute of a span.
+fn attribute(span: &SpanData, key: &str) -> Option<String> {
+    span.attributes.iter().find(|kv| kv.key.as_str() == key).map(|kv| kv.value.as_str().into_owned())
+}
+
+#[instrument_query(skip_all, capture_params(allow(name, limit), hash(email), max_len = 8))]
+async fn fetch_users(db: &SqlitePool, email: &str, name: &str, active: bool, limit: i64) -> Result<Vec<i64>, sqlx::Error> {
+    let query = "SELECT id FROM users WHERE email = ? AND name = ? AND active = ? LIMIT ?";
+    sqlx::query_scalar(query).bind(email).bind(name).bind(active).bind(limit).fetch_all(db).await
+}
+
+#[instrument_query(skip_all, capture_params(allow(user_id, name)))]
+async fn rename_user(db: &SqlitePool, user_id: i64, name: &str) -> Result<(), sqlx::Error> {
+    sqlx::query("UPDATE users SET active = FALSE WHERE id <> ?").bind(user_id).execute(db).await?;
+    let query = "UPDATE users SET name = ? WHERE id = ?";
+    sqlx::query(query).bind(name).bind(user_id).execute(db).await?;
+    Ok(())
+}
+
+#[instrument_query(skip_all, capture_params(allow(user_id), hash(name)))]
+async fn rename_next_user(db: &SqlitePool, user_id: i64, name: &str) -> Result<(), sqlx::Error> {
+    let user_id = user_id + 1;
+    let name = name.trim();
+    let query = "UPDATE users SET name = ? WHERE id = ?";
+    sqlx::query(query).bind(name).bind(user_id).execute(db).await?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn capture_params_redacts() {
+    let pool = common::pool().await;
+    let (exporter, _guard) = capture();
+
+    let users = fetch_users(&pool, "robin@example.com", "Robin Schroer", true, 10).await.unwrap();
+    assert_eq!(users, [1]);
+
+    let span = span(&exporter, "fetch_users");
+    assert!(attribute(&span, "db.sql.params.1").unwrap().starts_with("hmac-sha256:"));
+    assert_eq!(attribute(&span, "db.sql.params.2").as_deref(), Some("\"Robin S..."));
+    assert_eq!(attribute(&span, "db.sql.params.3").as_deref(), Some("?"));
+    assert_eq!(attribute(&span, "db.sql.params.4").as_deref(), Some("10"));
+    assert_eq!(attribute(&span, "db.sql.params.5"), None);
+}
+
+#[tokio::test]
+async fn capture_params_of_detected_query() {
+    let pool = common::pool().await;
+    let (exporter, _guard) = capture();
+
+    rename_user(&pool, 1, "Robin").await.unwrap();
+
+    let span = span(&exporter, "rename_user");
+    assert_eq!(attribute(&span, "db.sql.params.1").as_deref(), Some("\"Robin\""));
+    assert_eq!(attribute(&span, "db.sql.params.2").as_deref(), Some("1"));
+    assert_eq!(attribute(&span, "db.sql.params.3"), None);
+}
+
+#[tokio::test]
+async fn capture_params_redacts_rebound_arguments() {
+    let pool = common::pool().await;
+    let (exporter, _guard) = capture();
+
+    rename_next_user(&pool, 0, " Robin ").await.unwrap();
+
+    let span = span(&exporter, "rename_next_user");
+    assert_eq!(attribute(&span, "db.sql.params.1").as_deref(), Some("?"));
+    assert_eq!(attribute(&span, "db.sql.params.2").as_deref(), Some("?"));
+}
+
+#[tokio::test]
+async fn capture_params_digests() {
+    let pool = common::pool().await;
+    let (exporter, _guard) = capture();
+
+    // The key is global, so this is the only test depending on it.
+    let mut digests = vec![];
+    for key in ["first", "second", "first"] {
+        set_param_digest_key(key);
+        fetch_users(&pool, "robin@example.com", "Robin Schroer", true, 10).await.unwrap();
+        digests.push(attribute(&span(&exporter, "fetch_users"), "db.sql.params.1").unwrap());
+        exporter.reset();
+    }
+    set_param_digest_key("first");
+    fetch_users(&pool, "someone@example.com", "Robin Schroer", true, 10).await.unwrap();
+    let other = attribute(&span(&exporter, "fetch_users"), "db.sql.params.1").unwrap();
+
+    let hex = digests[0].strip_prefix("hmac-sha256:").unwrap();
+    assert!(hex.len() == 64 && hex.chars().all(|c| c.is_ascii_hexdigit()));
+    assert_ne!(digests[0], digests[1]);
+    assert_eq!(digests[0], digests[2]);
+    assert_ne!(digests[0], other);
+}
@@ -0,0 +1,419 @@
+mod common;
+
+use std::{
+    cell::RefCell,
+    collections::BTreeMap,
+    fmt::Debug,
+    sync::{
+        Arc, Mutex, Once,
+        atomic::{AtomicUsize, Ordering},
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use sqlx::{Sqlite, SqlitePool};
+use sqlx_datadog::{InstrumentedExecutor, InstrumentedPool, instrument_query};
+use tracing::{
+    Subscriber,
+    field::{Field, Visit},
+    span::{Attributes, Id, Record},
+};
+use tracing_subscriber::{
+    Layer,
+    layer::{Context, SubscriberExt},
+    registry::LookupSpan,
+};
+
+/// A closed span, with its fields formatted as strings.
+#[derive(Debug)]
+struct ClosedSpan {
+    name: &'static str,
+    parent: Option<&'static str>,
+    /// The names of all fields, including those without a value.
+    declared: Vec<&'static str>,
+    fields: BTreeMap<&'static str, String>,
+}
+
+impl ClosedSpan {
+    fn field(&self, name: &str) -> Option<&str> {
+        self.fields.get(name).map(String::as_str)
+    }
+}
+
+/// Collects the spans created on a test thread once they are closed.
+#[derive(Clone, Default)]
+struct Spans {
+    open: Arc<AtomicUsize>,
+    closed: Arc<Mutex<Vec<ClosedSpan>>>,
+}
+
+impl Spans {
+    /// Waits until all spans are closed, and returns them.
+    ///
+    /// The SQLite worker thread holds on to the spans of the statements it runs until it is done
+    /// with them, which may be after the statement returned.
+    fn closed(&self) -> std::sync::MutexGuard<'_, Vec<ClosedSpan>> {
+        let started_at = Instant::now();
+        while self.open.load(Ordering::SeqCst) > 0 {
+            assert!(started_at.elapsed() < Duration::from_secs(5), "spans still open");
+            thread::sleep(Duration::from_millis(1));
+        }
+        self.closed.lock().unwrap()
+    }
+
+    /// Returns the closed spans named `name`, in the order they were closed.
+    fn named(&self, name: &str) -> Vec<ClosedSpan> {
+        let mut spans = self.closed();
+        let (named, rest) = spans.drain(..).partition(|span| span.name == name);
+        *spans = rest;
+        named
+    }
+
+    /// Returns the closed query span with the resource `resource`.
+    fn query(&self, resource: &str) -> ClosedSpan {
+        let mut spans = self.closed();
+        let index = spans
+            .iter()
+            .position(|span| span.name == "sqlx.query" && span.field("resource") == Some(resource))
+            .unwrap_or_else(|| panic!("no query span for {resource:?} in {spans:#?}"));
+        spans.remove(index)
+    }
+}
+
+thread_local! {
+    /// Where the spans created on this thread are collected.
+    static SPANS: RefCell<Option<Spans>> = const { RefCell::new(None) };
+}
+
+/// The fields of an open span, and where to collect it.
+struct Captured {
+    spans: Spans,
+    fields: BTreeMap<&'static str, String>,
+}
+
+impl Visit for Captured {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.fields.insert(field.name(), value.to_string());
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn Debug) {
+        self.fields.insert(field.name(), format!("{value:?}"));
+    }
+}
+
+/// Passes spans to the [`Spans`] of the thread they were created on.
+struct Capture;
+
+impl<S: Subscriber + for<'a> LookupSpan<'a>> Layer<S> for Capture {
+    fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+        let Some(spans) = SPANS.with_borrow(Clone::clone) else {
+            return;
+        };
+        spans.open.fetch_add(1, Ordering::SeqCst);
+        let mut captured = Captured { spans, fields: BTreeMap::new() };
+        attrs.record(&mut captured);
+        ctx.span(id).unwrap().extensions_mut().insert(captured);
+    }
+
+    fn on_record(&self, id: &Id, values: &Record<'_>, ctx: Context<'_, S>) {
+        let span = ctx.span(id).unwrap();
+        if let Some(captured) = span.extensions_mut().get_mut::<Captured>() {
+            values.record(captured);
+        }
+    }
+
+    fn on_close(&self, id: Id, ctx: Context<'_, S>) {
+        let span = ctx.span(&id).unwrap();
+        let Some(Captured { spans, fields }) = span.extensions_mut().remove() else {
+            return;
+        };
+        let parent = span.parent().map(|parent| parent.name());
+        let declared = span.fields().iter().map(|field| field.name()).collect();
+        spans.closed.lock().unwrap().push(ClosedSpan { name: span.name(), parent, declared, fields });
+        spans.open.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// Captures the spans created on the current thread.
+///
+/// The subscriber is global, as spans are also closed on the SQLite worker thread.
+fn capture() -> Spans {
+    static SUBSCRIBER: Once = Once::new();
+    SUBSCRIBER.call_once(|| {
+        tracing::subscriber::set_global_default(tracing_subscriber::registry().with(Capture)).unwrap();
+    });
+    let spans = Spans::default();
+    SPANS.set(Some(spans.clone()));
+    spans
+}
+
+/// Opens the test database, see [`common::pool`], wrapped in an [`InstrumentedPool`].
+async fn pool() -> InstrumentedPool<Sqlite> {
+    InstrumentedPool::new(common::pool().await)
+}
+
+#[tokio::test]
+async fn pool_fetch() {
+    let pool = pool().await;
+    let spans = capture();
+
+    let rows = sqlx::query("SELECT id FROM users").fetch_all(&pool).await.unwrap();
+    assert_eq!(rows.len(), 3);
+    pool.close().await;
+
+    let query = spans.query("SELECT id FROM users");
+    assert_eq!(query.field("db.system"), Some("sqlite"));
+    assert_eq!(query.field("db.row_count"), Some("3"));
+    assert!(query.field("db.pool.wait_ms").is_some());
+
+    let [acquire] = spans.named("sqlx.pool.acquire").try_into().unwrap();
+    assert_eq!(acquire.parent, Some("sqlx.query"));
+    assert_eq!(acquire.field("resource"), Some("ACQUIRE"));
+    assert_eq!(acquire.field("db.pool.size"), Some("1"));
+    assert!(acquire.field("db.pool.idle").is_some());
+    assert!(acquire.field("db.pool.wait_ms").is_some());
+}
+
+#[tokio::test]
+async fn pool_fetch_optional() {
+    let pool = pool().await;
+    let spans = capture();
+
+    let row = sqlx::query("SELECT id FROM users WHERE id = ?").bind(2).fetch_optional(&pool).await.unwrap();
+    assert!(row.is_some());
+    let row = sqlx::query("SELECT id FROM users WHERE id = ?").bind(4).fetch_optional(&pool).await.unwrap();
+    assert!(row.is_none());
+    pool.close().await;
+
+    let found = spans.query("SELECT id FROM users WHERE id = ?");
+    assert_eq!(found.field("db.row_count"), Some("1"));
+    assert!(found.field("db.pool.wait_ms").is_some());
+    let missing = spans.query("SELECT id FROM users WHERE id = ?");
+    assert_eq!(missing.field("db.row_count"), Some("0"));
+
+    let acquires = spans.named("sqlx.pool.acquire");
+    assert_eq!(acquires.len(), 2);
+    assert!(acquires.iter().all(|acquire| acquire.parent == Some("sqlx.query")));
+}
+
+#[tokio::test]
+async fn executor_records_errors() {
+    let pool = pool().await;
+    let spans = capture();
+
+    let mut connection = pool.acquire().await.unwrap();
+    let result = sqlx::query("SELECT id FROM missing").fetch_all(InstrumentedExecutor::new(&mut *connection)).await;
+    assert!(result.is_err());
+    drop(connection);
+    pool.close().await;
+
+    let query = spans.query("SELECT id FROM missing");
+    assert_eq!(query.field("db.sql.table"), Some("missing"));
+    assert!(query.field("error.message").unwrap().contains("no such table"));
+    assert_eq!(query.field("db.row_count"), None);
+}
+
+#[tokio::test]
+async fn transaction_commit() {
+    let pool = pool().await;
+    let spans = capture();
+
+    let mut tx = pool.begin().await.unwrap();
+    sqlx::query("DELETE FROM users WHERE id = ?").bind(1).execute(&mut tx).await.unwrap();
+    tx.commit().await.unwrap();
+
+    let count: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM users").fetch_one(&pool).await.unwrap();
+    assert_eq!(count, 2);
+    pool.close().await;
+
+    let [transaction] = spans.named("sqlx.transaction").try_into().unwrap();
+    assert_eq!(transaction.field("db.transaction.outcome"), Some("commit"));
+    assert_eq!(transaction.field("db.system"), Some("sqlite"));
+
+    let begin = spans.query("BEGIN");
+    assert_eq!(begin.parent, Some("sqlx.transaction"));
+    assert!(begin.field("db.pool.wait_ms").is_some());
+    // One for the transaction, and one for counting the users.
+    let acquires = spans.named("sqlx.pool.acquire");
+    assert_eq!(acquires.len(), 2);
+    assert!(acquires.iter().all(|acquire| acquire.parent == Some("sqlx.query")));
+
+    let delete = spans.query("DELETE FROM users WHERE id = ?");
+    assert_eq!(delete.parent, Some("sqlx.transaction"));
+    assert_eq!(delete.field("db.row_count"), Some("1"));
+    assert_eq!(spans.query("COMMIT").parent, Some("sqlx.transaction"));
+}
+
+#[tokio::test]
+async fn transaction_rollback() {
+    let pool = pool().await;
+    let spans = capture();
+
+    let mut tx = pool.begin().await.unwrap();
+    sqlx::query("DELETE FROM users").execute(&mut tx).await.unwrap();
+    tx.rollback().await.unwrap();
+
+    let count: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM users").fetch_one(&pool).await.unwrap();
+    assert_eq!(count, 3);
+    pool.close().await;
+
+    let [transaction] = spans.named("sqlx.transaction").try_into().unwrap();
+    assert_eq!(transaction.field("db.transaction.outcome"), Some("rollback"));
+    assert_eq!(spans.query("ROLLBACK").parent, Some("sqlx.transaction"));
+}
+
+#[tokio::test]
+async fn transaction_implicit_rollback() {
+    let pool = pool().await;
+    let spans = capture();
+
+    let mut tx = pool.begin().await.unwrap();
+    sqlx::query("DELETE FROM users").execute(&mut tx).await.unwrap();
+    drop(tx);
+
+    let count: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM users").fetch_one(&pool).await.unwrap();
+    assert_eq!(count, 3);
+    pool.close().await;
+
+    let [transaction] = spans.named("sqlx.transaction").try_into().unwrap();
+    assert_eq!(transaction.field("db.transaction.outcome"), Some("implicit_rollback"));
+    assert_eq!(spans.query("DELETE FROM users").parent, Some("sqlx.transaction"));
+}
+
+#[instrument_query(skip(db), row_count)]
+async fn fetch_user_ids(db: &SqlitePool) -> Result<Vec<i64>, sqlx::Error> {
+    let query = "SELECT id FROM users WHERE id > 1";
+    sqlx::query_scalar(query).fetch_all(db).await
+}
+
+#[instrument_query(skip(db))]
+async fn fetch_user_id(db: &SqlitePool, id: i64) -> Result<i64, sqlx::Error> {
+    let query = "SELECT id FROM users WHERE id = ?";
+    sqlx::query_scalar(query).bind(id).fetch_one(db).await
+}
+
+#[instrument_query(skip(db))]
+async fn insert_user(db: &SqlitePool, id: i64) -> Result<(), sqlx::Error> {
+    let query = "INSERT INTO users (id) VALUES (?)";
+    sqlx::query(query).bind(id).execute(db).await?;
+    Ok(())
+}
+
+const COUNT_USERS: &str = "SELECT COUNT(*) FROM users WHERE id > 1";
+
+#[instrument_query(skip(db), query = COUNT_USERS)]
+async fn count_users(db: &SqlitePool) -> Result<i64, sqlx::Error> {
+    sqlx::query_scalar(COUNT_USERS).fetch_one(db).await
+}
+
+const OPERATION: &str = "sqlite.query";
+
+#[instrument_query(
+    skip(db),
+    peer_service = "users-db",
+    service = "users",
+    operation = OPERATION,
+    component = "sqlite"
+)]
+async fn delete_users(db: &SqlitePool) -> Result<(), sqlx::Error> {
+    let query = "DELETE FROM users";
+    sqlx::query(query).execute(db).await?;
+    Ok(())
+}
+
+#[instrument_query(skip(db))]
+async fn acquire_and_count_users(db: &InstrumentedPool<Sqlite>) -> Result<i64, sqlx::Error> {
+    let query = "SELECT COUNT(*) FROM users";
+    let mut connection = db.acquire().await?;
+    sqlx::query_scalar(query).fetch_one(&mut *connection).await
+}
+
+#[tokio::test]
+async fn instrument_query_records_pool_wait() {
+    let pool = pool().await;
+    let spans = capture();
+
+    assert_eq!(acquire_and_count_users(&pool).await.unwrap(), 3);
+    sqlx::query("SELECT id FROM users").fetch_all(&pool).await.unwrap();
+    pool.close().await;
+
+    let [span] = spans.named("acquire_and_count_users").try_into().unwrap();
+    assert!(span.field("db.pool.wait_ms").is_some());
+    let [acquire, _] = spans.named("sqlx.pool.acquire").try_into().unwrap();
+    assert_eq!(acquire.parent, Some("acquire_and_count_users"));
+    // Spans of the pool and of `instrument_query` look the same.
+    assert_eq!(spans.query("SELECT id FROM users").declared, span.declared);
+}
+
+#[tokio::test]
+async fn instrument_query_records_row_count() {
+    let pool = pool().await;
+    let spans = capture();
+
+    assert_eq!(fetch_user_ids(&pool).await.unwrap(), [2, 3]);
+    pool.close().await;
+
+    let [span] = spans.named("fetch_user_ids").try_into().unwrap();
+    assert_eq!(span.field("resource"), Some("SELECT id FROM users WHERE id > ?"));
+    assert_eq!(span.field("db.statement"), Some("SELECT id FROM users WHERE id > 1"));
+    assert_eq!(span.field("db.operation"), Some("SELECT"));
+    assert_eq!(span.field("db.sql.table"), Some("users"));
+    assert_eq!(span.field("db.system"), Some("sqlite"));
+    assert_eq!(span.field("db.row_count"), Some("2"));
+    assert_eq!(span.field("operation"), Some("sqlx.query"));
+    assert_eq!(span.field("component"), Some("sqlx"));
+    assert_eq!(span.field("error.type"), None);
+}
+
+#[tokio::test]
+async fn instrument_query_records_errors() {
+    let pool = pool().await;
+    let spans = capture();
+
+    assert!(matches!(fetch_user_id(&pool, 4).await, Err(sqlx::Error::RowNotFound)));
+    assert!(matches!(insert_user(&pool, 1).await, Err(sqlx::Error::Database(_))));
+    pool.close().await;
+
+    let [span] = spans.named("fetch_user_id").try_into().unwrap();
+    assert_eq!(span.field("error.type"), Some("RowNotFound"));
+    assert!(span.field("error.message").is_some());
+    assert_eq!(span.field("db.error.code"), None);
+
+    let [span] = spans.named("insert_user").try_into().unwrap();
+    assert_eq!(span.field("error.type"), Some("Database"));
+    assert!(span.field("error.message").unwrap().contains("UNIQUE constraint failed"));
+    // SQLITE_CONSTRAINT_PRIMARYKEY
+    assert_eq!(span.field("db.error.code"), Some("1555"));
+    assert_eq!(span.field("db.error.kind"), Some("unique_violation"));
+}
+
+#[tokio::test]
+async fn instrument_query_records_runtime_query() {
+    let pool = pool().await;
+    let spans = capture();
+
+    assert_eq!(count_users(&pool).await.unwrap(), 2);
+    pool.close().await;
+
+    let [span] = spans.named("count_users").try_into().unwrap();
+    assert_eq!(span.field("resource"), Some("SELECT COUNT(*) FROM users WHERE id > ?"));
+    assert_eq!(span.field("db.statement"), Some(COUNT_USERS));
+    assert_eq!(span.field("db.sql.table"), Some("users"));
+}
+
+#[tokio::test]
+async fn instrument_query_records_overrides() {
+    let pool = pool().await;
+    let spans = capture();
+
+    delete_users(&pool).await.unwrap();
+    pool.close().await;
+
+    let [span] = spans.named("delete_users").try_into().unwrap();
+    assert_eq!(span.field("peer.service"), Some("users-db"));
+    assert_eq!(span.field("service"), Some("users"));
+    assert_eq!(span.field("operation"), Some("sqlite.query"));
+    assert_eq!(span.field("component"), Some("sqlite"));
+    assert_eq!(span.field("db.system"), Some("sqlite"));
+}